@@ -1,11 +1,68 @@
-precision mediump float;
+#ifdef GBUFFER_MRT
+#extension GL_EXT_draw_buffers : require
+#endif
+
+#ifdef DERIVED_NORMALS
+#extension GL_OES_standard_derivatives : require
+#endif
+
+precision highp float;
 
 uniform mat4 view;
-uniform vec3 u_color;
+uniform float u_material_id;
+
+// Which G-buffer target to write when drawing one target at a time
+uniform int u_output;
 
 varying vec3 v_normal;
+varying vec3 v_position;
+varying float v_view_depth;
+
+// Must match star_compose.frag
+const float max_depth = 8.0;
+
+// Splits depth across two channels so it survives 8 bit targets
+vec2 encode_depth(float depth) {
+	float d = clamp(depth / max_depth, 0.0, 1.0) * 255.0;
+	return vec2(floor(d) / 255.0, fract(d));
+}
 
 void main() {
-	vec3 world_normal = mat3(view) * v_normal;
-	gl_FragColor = vec4(world_normal * 0.5 + 0.5, 1.0);
+#ifdef DERIVED_NORMALS
+	// Vertices are shared between faces and carry no normal, see Crystal::build_shared_faces.
+	//	The cross product of screen space derivatives faces the camera, so it's flipped on
+	//	back faces to point out of the crystal
+	vec3 normal = normalize(cross(dFdx(v_position), dFdy(v_position)));
+	if(!gl_FrontFacing) normal = -normal;
+#else
+	vec3 normal = v_normal;
+#endif
+
+	vec3 view_normal = mat3(view) * normal;
+
+	// Front and back faces go to separate targets. Blending leaves a target untouched
+	//	where alpha is zero, which works since a convex crystal covers each pixel with
+	//	at most one face of each kind
+	float front = gl_FrontFacing ? 1.0 : 0.0;
+	float back = 1.0 - front;
+
+	vec3 normal_data = view_normal * 0.5 + 0.5;
+	vec3 depth_data = vec3(encode_depth(v_view_depth), u_material_id / 255.0);
+
+#ifdef GBUFFER_MRT
+	gl_FragData[0] = vec4(normal_data, front);
+	gl_FragData[1] = vec4(normal_data, back);
+	gl_FragData[2] = vec4(depth_data, front);
+	gl_FragData[3] = vec4(depth_data, back);
+#else
+	if(u_output == 0) {
+		gl_FragColor = vec4(normal_data, front);
+	} else if(u_output == 1) {
+		gl_FragColor = vec4(normal_data, back);
+	} else if(u_output == 2) {
+		gl_FragColor = vec4(depth_data, front);
+	} else {
+		gl_FragColor = vec4(depth_data, back);
+	}
+#endif
 }
@@ -2,11 +2,16 @@ attribute vec3 position;
 attribute vec3 normal;
 
 uniform mat4 proj;
+uniform mat4 view;
 
 varying vec3 v_normal;
+varying vec3 v_position;
+varying float v_view_depth;
 
 void main() {
 	vec4 world_pos = vec4(position, 1.0);
 	gl_Position = proj * world_pos;
 	v_normal = normal;
+	v_position = world_pos.xyz;
+	v_view_depth = -(view * world_pos).z;
 }
@@ -0,0 +1,40 @@
+precision highp float;
+
+varying vec2 v_uv;
+
+uniform sampler2D u_color;
+uniform vec2 u_texel_size;
+
+// Only applied when downsampling the scene into the first bloom level
+uniform bool u_apply_threshold;
+uniform float u_threshold;
+uniform float u_soft_knee;
+
+// Keeps the part of the color above the threshold, with a quadratic falloff around it
+vec3 apply_threshold(vec3 color) {
+	float brightness = max(color.r, max(color.g, color.b));
+	float knee = u_threshold * u_soft_knee + 0.00001;
+
+	float soft = clamp(brightness - u_threshold + knee, 0.0, 2.0 * knee);
+	soft = soft * soft / (4.0 * knee);
+
+	return color * max(soft, brightness - u_threshold) / max(brightness, 0.00001);
+}
+
+void main() {
+	// Each bilinear tap averages a 2x2 block of the source
+	vec4 d = u_texel_size.xyxy * vec4(-1.0,-1.0, 1.0, 1.0);
+
+	vec3 color = texture2D(u_color, v_uv + d.xy).rgb
+		+ texture2D(u_color, v_uv + d.zy).rgb
+		+ texture2D(u_color, v_uv + d.xw).rgb
+		+ texture2D(u_color, v_uv + d.zw).rgb;
+
+	color *= 0.25;
+
+	if(u_apply_threshold) {
+		color = apply_threshold(color);
+	}
+
+	gl_FragColor = vec4(color, 1.0);
+}
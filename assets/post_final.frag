@@ -0,0 +1,71 @@
+precision highp float;
+
+varying vec2 v_uv;
+
+uniform sampler2D u_scene;
+uniform sampler2D u_bloom;
+
+uniform bool u_bloom_enabled;
+uniform float u_bloom_intensity;
+
+uniform bool u_tone_map_enabled;
+uniform float u_exposure;
+uniform float u_white_point;
+
+uniform bool u_vignette_enabled;
+uniform float u_vignette_strength;
+uniform float u_vignette_radius;
+uniform float u_vignette_softness;
+
+uniform bool u_grain_enabled;
+uniform float u_grain_intensity;
+
+uniform float u_aspect;
+uniform float u_time;
+
+// Hable's filmic curve
+vec3 filmic_curve(vec3 x) {
+	const float a = 0.15;
+	const float b = 0.50;
+	const float c = 0.10;
+	const float d = 0.20;
+	const float e = 0.02;
+	const float f = 0.30;
+
+	return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+vec3 tone_map(vec3 color) {
+	return filmic_curve(color * u_exposure) / filmic_curve(vec3(u_white_point));
+}
+
+float hash(vec2 p) {
+	vec3 p3 = fract(p.xyx * 0.1031);
+	p3 += dot(p3, p3.yzx + 33.33);
+	return fract((p3.x + p3.y) * p3.z);
+}
+
+void main() {
+	vec3 color = texture2D(u_scene, v_uv).rgb;
+
+	if(u_bloom_enabled) {
+		color += texture2D(u_bloom, v_uv).rgb * u_bloom_intensity;
+	}
+
+	if(u_tone_map_enabled) {
+		color = tone_map(max(color, vec3(0.0)));
+	}
+
+	if(u_vignette_enabled) {
+		float dist = length((v_uv - 0.5) * vec2(u_aspect, 1.0));
+		float falloff = smoothstep(u_vignette_radius - u_vignette_softness, u_vignette_radius, dist);
+		color *= 1.0 - falloff * u_vignette_strength;
+	}
+
+	if(u_grain_enabled) {
+		float noise = hash(gl_FragCoord.xy + fract(u_time * 7.13) * 1000.0) - 0.5;
+		color += noise * u_grain_intensity;
+	}
+
+	gl_FragColor = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,26 @@
+precision highp float;
+
+varying vec2 v_uv;
+
+uniform sampler2D u_color;
+uniform vec2 u_texel_size;
+uniform float u_radius;
+
+void main() {
+	// 3x3 tent filter, added onto the next larger level
+	vec4 d = u_texel_size.xyxy * vec4(1.0, 1.0,-1.0, 0.0) * u_radius;
+
+	vec3 color = texture2D(u_color, v_uv - d.xy).rgb;
+	color += texture2D(u_color, v_uv - d.wy).rgb * 2.0;
+	color += texture2D(u_color, v_uv - d.zy).rgb;
+
+	color += texture2D(u_color, v_uv + d.zw).rgb * 2.0;
+	color += texture2D(u_color, v_uv).rgb * 4.0;
+	color += texture2D(u_color, v_uv + d.xw).rgb * 2.0;
+
+	color += texture2D(u_color, v_uv + d.zy).rgb;
+	color += texture2D(u_color, v_uv + d.wy).rgb * 2.0;
+	color += texture2D(u_color, v_uv + d.xy).rgb;
+
+	gl_FragColor = vec4(color / 16.0, 1.0);
+}
@@ -1,14 +1,30 @@
 attribute vec3 position;
-attribute vec3 normal;
+attribute float color;
+attribute float point_size;
+attribute float motion_blur_step;
 
 uniform mat4 proj;
+uniform mat4 view;
 
-varying vec3 v_normal;
+// Rotations at the start and end of the frame, as quaternions
+uniform vec4 u_rotation_from;
+uniform vec4 u_rotation_to;
+
+// Render resolution relative to the viewport, keeps stars the same size on screen
+uniform float u_resolution_scale;
+
+varying float v_color;
+
+vec3 rotate(vec3 v, vec4 q) {
+	return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
 
 void main() {
-	vec4 world_pos = vec4(position, 1.0);
-	gl_Position = proj * world_pos;
-	gl_PointSize = normal.g * 2.0;
+	vec4 rotation = normalize(mix(u_rotation_from, u_rotation_to, motion_blur_step));
+	vec3 view_pos = rotate(position, vec4(-rotation.xyz, rotation.w));
+
+	gl_Position = proj * view * vec4(view_pos, 1.0);
+	gl_PointSize = max(point_size * 2.0 * u_resolution_scale, 1.0);
 
-	v_normal = normal;
+	v_color = color;
 }
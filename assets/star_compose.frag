@@ -3,10 +3,15 @@ precision highp float;
 varying vec2 v_uv;
 
 uniform sampler2D u_bgcolor;
-uniform sampler2D u_color0;
-uniform sampler2D u_color1;
-uniform sampler2D u_depth0;
-uniform sampler2D u_depth1;
+uniform sampler2D u_front_normal;
+uniform sampler2D u_back_normal;
+uniform sampler2D u_front_depth;
+uniform sampler2D u_back_depth;
+
+// The star field around the camera, for refracted rays that leave the screen
+uniform samplerCube u_star_cubemap;
+uniform bool u_use_star_cubemap;
+uniform mat4 u_inv_view_rotation;
 
 uniform mat4 proj;
 uniform mat4 inv_proj;
@@ -14,52 +19,60 @@ uniform mat4 inv_proj;
 uniform float u_refractive_index;
 uniform float u_time;
 
-// float linearise_depth(float z_b) {
-// 	float zNear = 0.005;
-// 	float zFar = 1000.0;
+// Iterations of the refraction march, at most max_march_steps
+uniform float u_march_steps;
+
+// Must match crystal.frag
+const float max_depth = 8.0;
 
-// 	float z_n = 2.0 * z_b - 1.0;
-// 	return 2.0 * zNear * zFar / (zFar + zNear - z_n * (zFar - zNear));
-// }
+const float max_march_steps = 16.0;
 
-// float sample_back_depth(vec2 uv) {
-// 	return linearise_depth(texture2D(u_depth0, uv).r);
-// }
+// Returns linear view depth, or a negative value where the crystal doesn't cover uv
+float sample_depth(sampler2D target, vec2 uv) {
+	vec4 data = texture2D(target, uv);
+	if(data.a < 0.5) return -1.0;
+
+	return (floor(data.r * 255.0 + 0.5) + data.g) / 255.0 * max_depth;
+}
 
 void main() {
 	vec4 bgcolor = texture2D(u_bgcolor, v_uv);
-	vec4 front_color = texture2D(u_color1, v_uv);
+	float front_depth = sample_depth(u_front_depth, v_uv);
 
-	if(front_color.a < 0.5) {
+	if(front_depth < 0.0) {
 		gl_FragColor = vec4(bgcolor.rgb, 1.0);
 		return;
 	}
 
-	vec3 front_normal = normalize(front_color.rgb * 2.0 - 1.0);
-	float front_depth = texture2D(u_depth1, v_uv).r;
+	vec3 front_normal = normalize(texture2D(u_front_normal, v_uv).rgb * 2.0 - 1.0);
 
-	vec4 world_pos = inv_proj * vec4(v_uv * 2.0 - 1.0, front_depth, 1.0);
-	world_pos /= world_pos.w;
+	vec4 near_pos = inv_proj * vec4(v_uv * 2.0 - 1.0, -1.0, 1.0);
+	vec3 view_ray = near_pos.xyz / near_pos.w;
+	view_ray /= -view_ray.z;
 
-	vec3 view_dir = normalize(world_pos.xyz);
+	vec3 world_pos = view_ray * front_depth;
+	vec3 view_dir = normalize(world_pos);
 
 	vec3 dir = refract(view_dir, front_normal, u_refractive_index);
 	
-	vec3 ray_pos = world_pos.xyz;
+	vec3 ray_pos = world_pos;
 
 	vec2 star_sample_pos = v_uv;
 	float step = 2.0;
-	float subdivisions = 4.0;
+	float subdivisions = floor(u_march_steps / 4.0);
 
 	vec3 back_normal = vec3(0.0);
+	vec3 exit_dir = dir;
+
+	for(float i = 0.0; i < max_march_steps; i += 1.0) {
+		if(i >= u_march_steps) break;
 
-	for(float i = 0.0; i < 16.0; i += 1.0) {
 		vec4 screen_pos = proj * vec4(ray_pos, 1.0);
 		screen_pos /= screen_pos.w;
 		screen_pos.xy = screen_pos.xy * 0.5 + 0.5;
 
-		float depth0 = texture2D(u_depth0, screen_pos.xy).r;
-		bool outside_crystal = screen_pos.z > depth0 || depth0 > 0.995;
+		float back_depth = sample_depth(u_back_depth, screen_pos.xy);
+		bool outside_crystal = back_depth < 0.0 || -ray_pos.z > back_depth;
 
 		if(step > 0.0) {
 			if(outside_crystal) {
@@ -74,10 +87,10 @@ void main() {
 		ray_pos += dir * step;
 
 		if(step > 0.0 && subdivisions < 0.0) {
-			vec3 back_color = texture2D(u_color0, screen_pos.xy).rgb;
+			vec3 back_color = texture2D(u_back_normal, screen_pos.xy).rgb;
 			back_normal = normalize(back_color * 2.0 - 1.0);
 
-			vec3 exit_dir = refract(dir, -back_normal, u_refractive_index);
+			exit_dir = refract(dir, -back_normal, u_refractive_index);
 			vec4 screen_pos = proj * vec4(ray_pos + exit_dir * 2.0, 1.0);
 			screen_pos /= screen_pos.w;
 
@@ -86,7 +99,7 @@ void main() {
 		}
 	}
 
-	float travel_dist = length(ray_pos - world_pos.xyz);
+	float travel_dist = length(ray_pos - world_pos);
 
 	const vec3 lightdir = normalize(vec3(2.0, 2.0,-1.0));
 	const float inv_clarity = 0.15;
@@ -99,7 +112,14 @@ void main() {
 	float front_ndotl = clamp(dot(front_normal, lightdir) + 0.2, 0.0, 1.0);
 	vec3 front_spec = vec3(0.0, 0.2, 1.0) * inv_clarity * front_ndotl + pow(front_ndotl, light_pwr) * light_str;
 
-	vec3 color = front_spec + (back_spec * (1.0 - travel_dist * 0.1)) + texture2D(u_bgcolor, star_sample_pos).rgb;
+	vec3 refracted_color;
+	if(u_use_star_cubemap) {
+		refracted_color = textureCube(u_star_cubemap, mat3(u_inv_view_rotation) * exit_dir).rgb;
+	} else {
+		refracted_color = texture2D(u_bgcolor, star_sample_pos).rgb;
+	}
+
+	vec3 color = front_spec + (back_spec * (1.0 - travel_dist * 0.1)) + refracted_color;
 
 	// Inner glow
 	color += vec3(1.0, 0.0, 0.27) * clamp((travel_dist - 0.6) * 0.1, 0.0, 0.25);
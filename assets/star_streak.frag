@@ -0,0 +1,27 @@
+precision mediump float;
+
+varying float v_color;
+varying float v_radius;
+varying vec2 v_local;
+varying vec2 v_segment;
+
+uniform float u_time;
+
+void main() {
+	// Evaluate the star shape around the closest point on the streak
+	float segment_length2 = dot(v_segment, v_segment);
+	float t = segment_length2 > 0.0 ? clamp(dot(v_local, v_segment) / segment_length2, 0.0, 1.0) : 0.0;
+	vec2 p = (v_local - v_segment * t) / v_radius;
+
+	vec3 color = vec3(v_color);
+
+	float ang_vel = cos(v_color * 73.0) * 3.0;
+
+	float dist = length(p);
+	float ang = atan(p.y, p.x);
+	float off = v_color * 100.0 + ang_vel * u_time;
+
+	float a = step(dist, 0.5 + cos(ang * 4.0 + off)*0.2);
+
+	gl_FragColor = vec4(color, a);
+}
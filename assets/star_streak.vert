@@ -0,0 +1,70 @@
+attribute vec3 position;
+attribute float color;
+attribute float point_size;
+
+// x: 0 at the previous position, 1 at the current position. y: which side of the streak
+attribute vec2 uv;
+
+uniform mat4 proj;
+uniform mat4 view;
+
+// Rotations at the start and end of the frame, as quaternions
+uniform vec4 u_rotation_from;
+uniform vec4 u_rotation_to;
+
+uniform vec2 u_viewport_size;
+
+// Render resolution relative to the viewport, keeps stars the same size on screen
+uniform float u_resolution_scale;
+
+varying float v_color;
+varying float v_radius;
+
+// Position relative to the start of the streak, and the streak itself, in pixels
+varying vec2 v_local;
+varying vec2 v_segment;
+
+vec3 rotate(vec3 v, vec4 q) {
+	return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+vec4 project(vec4 rotation) {
+	rotation = normalize(rotation);
+	return proj * view * vec4(rotate(position, vec4(-rotation.xyz, rotation.w)), 1.0);
+}
+
+void main() {
+	vec4 clip_from = project(u_rotation_from);
+	vec4 clip_to = project(u_rotation_to);
+
+	v_color = color;
+
+	// Cull stars that are behind the camera at either end of the frame
+	if(clip_from.w <= 0.0 || clip_to.w <= 0.0) {
+		gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+		return;
+	}
+
+	vec2 half_viewport = u_viewport_size * 0.5;
+	vec2 screen_from = clip_from.xy / clip_from.w * half_viewport;
+	vec2 screen_to = clip_to.xy / clip_to.w * half_viewport;
+
+	vec2 segment = screen_to - screen_from;
+	float segment_length = length(segment);
+	vec2 dir = segment_length > 0.001 ? segment / segment_length : vec2(1.0, 0.0);
+	vec2 perp = vec2(-dir.y, dir.x);
+
+	// Matches the half size of the point sprites drawn by star.vert
+	float radius = max(point_size * u_resolution_scale, 0.5);
+
+	vec2 local = mix(-dir * radius, segment + dir * radius, uv.x) + perp * uv.y * radius;
+
+	v_radius = radius;
+	v_local = local;
+	v_segment = segment;
+
+	vec2 screen_pos = (screen_from + local) / half_viewport;
+	float depth = mix(clip_from.z / clip_from.w, clip_to.z / clip_to.w, uv.x);
+
+	gl_Position = vec4(screen_pos, depth, 1.0);
+}
@@ -55,7 +55,7 @@ fn main() {
 	let dest = env::var("OUT_DIR").unwrap();
 	let mut file = File::create(&Path::new(&dest).join("gl_bindings.rs")).unwrap();
 
-	Registry::new(Api::Gles2, (2, 1), Profile::Core, Fallbacks::All, [])
+	Registry::new(Api::Gles2, (2, 1), Profile::Core, Fallbacks::All, ["GL_OES_vertex_array_object", "GL_ANGLE_instanced_arrays", "GL_EXT_draw_buffers"])
 		.write_bindings(StaticGenerator, &mut file)
 		.unwrap();
 
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+pub fn duration_to_secs(d: Duration) -> f64 {
+	d.as_secs() as f64 + d.subsec_nanos() as f64 / 1000_000_000.0
+}
+
+// Measures real time between frames
+pub struct Clock {
+	prev_tick: Instant,
+}
+
+impl Clock {
+	pub fn new() -> Self {
+		Clock { prev_tick: Instant::now() }
+	}
+
+	// Returns the time since the last tick, or since the clock was created or reset
+	pub fn tick(&mut self) -> Duration {
+		let now = Instant::now();
+		let elapsed = now - self.prev_tick;
+		self.prev_tick = now;
+		elapsed
+	}
+
+	// Forgets time that passed while nothing was ticking, e.g. while paused
+	pub fn reset(&mut self) {
+		self.prev_tick = Instant::now();
+	}
+}
+
+// Accumulates frame time and splits it into steps of a fixed length, so that a simulation
+//	advances the same way regardless of frame rate. What's left over is exposed as
+//	interpolation_factor, for blending between the last two simulated states when rendering
+pub struct FixedTimestep {
+	pub step: f64,
+
+	// Time beyond this many steps in one advance is dropped, so that a long stall can't
+	//	queue up more simulation than a frame can catch up on
+	pub max_steps: u32,
+
+	accumulator: f64,
+}
+
+impl FixedTimestep {
+	pub fn new(step: f64, max_steps: u32) -> Self {
+		assert!(step > 0.0);
+		FixedTimestep { step, max_steps, accumulator: 0.0 }
+	}
+
+	// Adds dt seconds, and returns how many steps should be simulated
+	pub fn advance(&mut self, dt: f64) -> u32 {
+		self.accumulator += dt.max(0.0);
+
+		let steps = (self.accumulator / self.step).floor();
+		self.accumulator -= steps * self.step;
+
+		(steps as u32).min(self.max_steps)
+	}
+
+	// How far between the previous and current simulated states the current time is
+	pub fn interpolation_factor(&self) -> f32 {
+		(self.accumulator / self.step).max(0.0).min(1.0) as f32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Runs frames of length frame_time for duration seconds, returning the total steps taken
+	fn run(timestep: &mut FixedTimestep, frame_time: f64, duration: f64) -> u32 {
+		let frames = (duration / frame_time).round() as u32;
+		(0..frames).map(|_| timestep.advance(frame_time)).sum()
+	}
+
+	#[test]
+	fn steps_are_independent_of_frame_rate() {
+		// 5/6s is a whole number of frames at both rates, and lands between 50Hz steps
+		let duration = 5.0 / 6.0;
+
+		let mut slow = FixedTimestep::new(1.0 / 50.0, 8);
+		let mut fast = FixedTimestep::new(1.0 / 50.0, 8);
+
+		assert_eq!(run(&mut slow, 1.0 / 30.0, duration), 41);
+		assert_eq!(run(&mut fast, 1.0 / 144.0, duration), 41);
+
+		let expected_alpha = (duration * 50.0 - 41.0) as f32;
+		assert!((slow.interpolation_factor() - expected_alpha).abs() < 0.0001);
+		assert!((fast.interpolation_factor() - expected_alpha).abs() < 0.0001);
+	}
+
+	#[test]
+	fn stalls_are_capped() {
+		let mut timestep = FixedTimestep::new(1.0 / 60.0, 8);
+
+		assert_eq!(timestep.advance(2.0), 8);
+
+		// The rest of the stall is dropped rather than carried into the next frames
+		assert!(timestep.interpolation_factor() < 1.0);
+		assert_eq!(timestep.advance(0.0), 0);
+		assert_eq!(timestep.advance(1.0 / 60.0), 1);
+	}
+
+	#[test]
+	fn negative_time_is_ignored() {
+		let mut timestep = FixedTimestep::new(1.0 / 60.0, 8);
+
+		assert_eq!(timestep.advance(-1.0), 0);
+		assert_eq!(timestep.advance(1.0 / 60.0), 1);
+	}
+}
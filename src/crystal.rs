@@ -131,6 +131,8 @@ impl Crystal {
 		}
 	}
 
+	// Each face gets its own flat normal, so vertices can only be shared between the triangles
+	//	of one face. Used when normals can't be derived in the fragment shader
 	pub fn build_faces(&self, mb: &mut mesh_builder::MeshBuilder) {
 		use mesh_builder::Vertex as MBVert;
 
@@ -157,6 +159,31 @@ impl Crystal {
 		}
 	}
 
+	// Positions only, with every vertex shared by all of the faces around it. The faces are
+	//	flat, so normals are rebuilt from screen space derivatives in crystal.frag instead
+	pub fn build_shared_faces(&self, mb: &mut mesh_builder::MeshBuilder) {
+		use mesh_builder::Vertex as MBVert;
+
+		let indices = self.verts.iter()
+			.map(|&Vertex(v, _)| mb.add_shared_vert(MBVert::new(v)))
+			.collect::<Vec<_>>();
+
+		// Fanned from the first vertex, the same as add_convex_poly in build_faces
+		for &Face(start) in self.faces.iter() {
+			let first = indices[self.edges[start].vertex];
+
+			let mut it = self.edge_next(start);
+			let mut next = self.edge_next(it);
+
+			while next != start {
+				mb.add_triangle_indices(first, indices[self.edges[it].vertex], indices[self.edges[next].vertex]);
+
+				it = next;
+				next = self.edge_next(next);
+			}
+		}
+	}
+
 	pub fn generate(&mut self) {
 		self.verts.clear();
 		self.edges.clear();
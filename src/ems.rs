@@ -3,8 +3,10 @@
 use std::mem::transmute;
 use std::ffi::CString;
 use std::ptr;
+use std::slice;
 
 use common::Vec2i;
+use rendering::image::{Image, Rgba8};
 
 use ::MainContext;
 
@@ -67,6 +69,27 @@ struct EmscriptenPointerlockChangeEvent {
 	// ..
 }
 
+#[repr(C)]
+struct EmscriptenUiEvent {
+	detail: i32,
+	_document_body_client: [i32; 2],
+	window_inner_width: i32,
+	window_inner_height: i32,
+	// ..
+}
+
+#[repr(C)]
+struct EmscriptenOrientationChangeEvent {
+	orientation_index: i32,
+	orientation_angle: i32,
+}
+
+#[repr(C)]
+struct EmscriptenVisibilityChangeEvent {
+	hidden: i32,
+	visibility_state: i32,
+}
+
 #[repr(C)]
 pub struct EmscriptenWebGLContextAttributes {
 	pub alpha: i32, // NOTE: this enables alpha blending of the *canvas itself*
@@ -84,6 +107,9 @@ pub struct EmscriptenWebGLContextAttributes {
 	pub enable_extensions_by_default: i32,
 }
 
+// Target selector for event callbacks registered on the window rather than an element
+const EVENT_TARGET_WINDOW: &'static [u8] = b"#window\0";
+
 pub const RESULT_SUCCESS: i32             =  0;
 pub const RESULT_DEFERRED: i32            =  1;
 pub const RESULT_NOT_SUPPORTED: i32       = -1;
@@ -101,12 +127,20 @@ type EmPointerLockChangeCallback = extern fn(etype: i32, evt: *const EmscriptenP
 type EmMouseCallback = extern fn(etype: i32, evt: *const EmscriptenMouseEvent, ud: *mut u8) -> i32;
 type EmTouchCallback = extern fn(etype: i32, evt: *const EmscriptenTouchEvent, ud: *mut u8) -> i32;
 type EmKeyCallback = extern fn(etype: i32, evt: *const EmscriptenKeyboardEvent, ud: *mut u8) -> i32;
+type EmWebGLContextCallback = extern fn(etype: i32, reserved: *const u8, ud: *mut u8) -> i32;
+type EmVisibilityChangeCallback = extern fn(etype: i32, evt: *const EmscriptenVisibilityChangeEvent, ud: *mut u8) -> i32;
+type EmUiCallback = extern fn(etype: i32, evt: *const EmscriptenUiEvent, ud: *mut u8) -> i32;
+type EmOrientationChangeCallback = extern fn(etype: i32, evt: *const EmscriptenOrientationChangeEvent, ud: *mut u8) -> i32;
 type EmArgCallback = extern fn(ud: *mut u8);
+type EmWgetDataCallback = extern fn(ud: *mut u8, data: *const u8, size: i32);
+type EmPreloadDataCallback = extern fn(ud: *mut u8, file_name: *const u8);
 
 #[allow(improper_ctypes)]
 extern {
 	fn emscripten_set_main_loop_arg(func: extern fn(arg: *mut u8), arg: *mut u8, fps: i32, simulate_infinite_loop: i32);
 	fn emscripten_exit_with_live_runtime();
+	fn emscripten_pause_main_loop();
+	fn emscripten_resume_main_loop();
 
 	pub fn emscripten_set_socket_open_callback(ud: *mut u8, callback: EmSocketCallback);
 	pub fn emscripten_set_socket_close_callback(ud: *mut u8, callback: EmSocketCallback);
@@ -126,17 +160,34 @@ extern {
 	fn emscripten_set_keydown_callback(target: *const u8, ud: *mut u8, useCapture: i32, cb: EmKeyCallback);
 	fn emscripten_set_keyup_callback(target: *const u8, ud: *mut u8, useCapture: i32, cb: EmKeyCallback);
 
+	fn emscripten_set_resize_callback(target: *const u8, ud: *mut u8, useCapture: i32, cb: EmUiCallback) -> i32;
+	fn emscripten_set_orientationchange_callback(ud: *mut u8, useCapture: i32, cb: EmOrientationChangeCallback);
+
+	fn emscripten_set_visibilitychange_callback(ud: *mut u8, useCapture: i32, cb: EmVisibilityChangeCallback);
+
+	fn emscripten_set_webglcontextlost_callback(target: *const u8, ud: *mut u8, useCapture: i32, cb: EmWebGLContextCallback);
+	fn emscripten_set_webglcontextrestored_callback(target: *const u8, ud: *mut u8, useCapture: i32, cb: EmWebGLContextCallback);
+
 	fn emscripten_request_pointerlock(target: *const u8, defer: i32) -> i32;
 	fn emscripten_request_fullscreen(target: *const u8, defer: i32) -> i32;
 
 	pub fn emscripten_async_call(callback: EmArgCallback, ud: *mut u8, millis: i32);
+	fn emscripten_async_wget_data(url: *const u8, ud: *mut u8, onload: EmWgetDataCallback, onerror: EmArgCallback);
 	pub fn emscripten_asm_const_int(s: *const u8, ...) -> i32;
 
+	fn emscripten_run_preload_plugins_data(data: *const u8, size: i32, suffix: *const u8, ud: *mut u8,
+		onload: EmPreloadDataCallback, onerror: EmArgCallback);
+	fn emscripten_get_preloaded_image_data(path: *const u8, w: *mut i32, h: *mut i32) -> *mut u8;
+
+	fn free(ptr: *mut u8);
+
 	pub fn emscripten_webgl_init_context_attributes(attribs: *mut EmscriptenWebGLContextAttributes);
 	pub fn emscripten_webgl_create_context(target: *const i8, attribs: *const EmscriptenWebGLContextAttributes) -> EmWebGLContext;
 	pub fn emscripten_webgl_make_context_current(context: EmWebGLContext) -> i32;
 	pub fn emscripten_webgl_destroy_context(context: EmWebGLContext) -> i32;
 	pub fn emscripten_webgl_get_current_context() -> EmWebGLContext;
+	pub fn emscripten_webgl_get_context_attributes(context: EmWebGLContext, attribs: *mut EmscriptenWebGLContextAttributes) -> i32;
+	pub fn emscripten_webgl_enable_extension(context: EmWebGLContext, extension: *const u8) -> i32;
 }
 
 pub trait Interop {
@@ -185,6 +236,83 @@ macro_rules! js {
 }
 
 
+// Fetches url relative to the page, and calls callback with the response body, or None on failure
+pub fn fetch_async<F>(url: &str, callback: F) where F: FnOnce(Option<&[u8]>) + 'static {
+	extern fn on_load<F: FnOnce(Option<&[u8]>)>(ud: *mut u8, data: *const u8, size: i32) {
+		let callback = unsafe { *Box::from_raw(ud as *mut F) };
+		let data = unsafe { slice::from_raw_parts(data, size as usize) };
+		callback(Some(data));
+	}
+
+	extern fn on_error<F: FnOnce(Option<&[u8]>)>(ud: *mut u8) {
+		let callback = unsafe { *Box::from_raw(ud as *mut F) };
+		callback(None);
+	}
+
+	let url = CString::new(url).unwrap();
+	let ud = Box::into_raw(Box::new(callback)) as *mut u8;
+
+	unsafe {
+		emscripten_async_wget_data(url.as_ptr() as _, ud, on_load::<F>, on_error::<F>);
+	}
+}
+
+// Offers data to the user as a file download
+pub fn download_file(name: &str, data: &[u8], mime_type: &str) {
+	js! { (name, data.as_ptr(), data.len() as i32, mime_type) b"
+		var blob = new Blob([HEAPU8.subarray($1, $1 + $2)], {type: Pointer_stringify($3)});
+		var link = document.createElement('a');
+		link.href = URL.createObjectURL(blob);
+		link.download = Pointer_stringify($0);
+
+		document.body.appendChild(link);
+		link.click();
+		document.body.removeChild(link);
+
+		setTimeout(function() { URL.revokeObjectURL(link.href); }, 0);
+	\0" };
+}
+
+// Decodes an image in any format the browser understands (e.g. jpg), where suffix is
+//	the file extension. Calls callback with the pixels in rows ordered top down, or None on failure
+pub fn decode_image_async<F>(data: Vec<u8>, suffix: &str, callback: F) where F: FnOnce(Option<Image<Rgba8>>) + 'static {
+	// The encoded data has to outlive the decode
+	type Pending<F> = (Vec<u8>, F);
+
+	extern fn on_load<F: FnOnce(Option<Image<Rgba8>>)>(ud: *mut u8, file_name: *const u8) {
+		let (_, callback) = unsafe { *Box::from_raw(ud as *mut Pending<F>) };
+
+		let (mut w, mut h) = (0, 0);
+		let pixels = unsafe { emscripten_get_preloaded_image_data(file_name, &mut w, &mut h) };
+
+		if pixels.is_null() {
+			callback(None);
+			return
+		}
+
+		let image = unsafe {
+			let data = slice::from_raw_parts(pixels as *const Rgba8, (w * h) as usize).to_vec();
+			free(pixels);
+			Image::new(Vec2i::new(w, h), data)
+		};
+
+		callback(Some(image));
+	}
+
+	extern fn on_error<F: FnOnce(Option<Image<Rgba8>>)>(ud: *mut u8) {
+		let (_, callback) = unsafe { *Box::from_raw(ud as *mut Pending<F>) };
+		callback(None);
+	}
+
+	let suffix = CString::new(suffix).unwrap();
+	let (data_ptr, size) = (data.as_ptr(), data.len() as i32);
+	let ud = Box::into_raw(Box::new((data, callback))) as *mut u8;
+
+	unsafe {
+		emscripten_run_preload_plugins_data(data_ptr, size, suffix.as_ptr() as _, ud, on_load::<F>, on_error::<F>);
+	}
+}
+
 pub fn register_callbacks(ctx: *mut MainContext) {
 	unsafe {
 		emscripten_set_touchstart_callback(ptr::null(), ctx as *mut u8, 1, on_touch_down);
@@ -196,6 +324,17 @@ pub fn register_callbacks(ctx: *mut MainContext) {
 		emscripten_set_mouseup_callback(ptr::null(), ctx as *mut u8, 0, on_mouse_up);
 		emscripten_set_mousemove_callback(ptr::null(), ctx as *mut u8, 0, on_mouse_move);
 
+		emscripten_set_keydown_callback(ptr::null(), ctx as *mut u8, 0, on_key_down);
+
+		let result = emscripten_set_resize_callback(EVENT_TARGET_WINDOW.as_ptr(), ctx as *mut u8, 0, on_resize);
+		assert!(result == RESULT_SUCCESS, "Failed to register resize callback: {}", result);
+		emscripten_set_orientationchange_callback(ctx as *mut u8, 0, on_orientation_change);
+
+		emscripten_set_visibilitychange_callback(ctx as *mut u8, 0, on_visibility_change);
+
+		emscripten_set_webglcontextlost_callback(b"canvas\0".as_ptr(), ctx as *mut u8, 0, on_context_lost);
+		emscripten_set_webglcontextrestored_callback(b"canvas\0".as_ptr(), ctx as *mut u8, 0, on_context_restored);
+
 		emscripten_set_main_loop_arg(on_update, ctx as *mut u8, 0, 1);
 	}
 }
@@ -203,10 +342,71 @@ pub fn register_callbacks(ctx: *mut MainContext) {
 extern fn on_update(ud: *mut u8) {
 	let ctx: &mut MainContext = unsafe{ transmute(ud) };
 
+	// Nothing can be drawn until the context comes back
+	if ctx.context_lost { return }
+
 	ctx.on_update();
 	ctx.on_render();
 }
 
+// Stops the main loop entirely while the page is hidden, rather than relying on the browser
+//	to throttle it
+extern fn on_visibility_change(_: i32, ev: *const EmscriptenVisibilityChangeEvent, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	let ev = unsafe { &*ev };
+
+	if ev.hidden != 0 {
+		unsafe { emscripten_pause_main_loop(); }
+		ctx.on_hidden();
+	} else {
+		ctx.on_shown();
+		unsafe { emscripten_resume_main_loop(); }
+	}
+
+	0
+}
+
+// The browser only tries to restore the context if the lost event is default prevented,
+//	which returning true does
+extern fn on_context_lost(_: i32, _: *const u8, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	ctx.on_context_lost();
+
+	1
+}
+
+extern fn on_context_restored(_: i32, _: *const u8, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	ctx.on_context_restored();
+
+	0
+}
+
+extern fn on_resize(_: i32, _ev: *const EmscriptenUiEvent, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	ctx.on_resize();
+
+	0
+}
+
+// Some mobile browsers fire orientationchange before the window has been laid out at its
+//	new size, so the canvas is fitted again once layout has had time to settle
+extern fn on_orientation_change(_: i32, _ev: *const EmscriptenOrientationChangeEvent, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	ctx.on_resize();
+
+	unsafe {
+		emscripten_async_call(on_deferred_resize, ud, 300);
+	}
+
+	0
+}
+
+extern fn on_deferred_resize(ud: *mut u8) {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	ctx.on_resize();
+}
+
 extern fn on_touch_down(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
 	let ctx: &mut MainContext = unsafe{ transmute(ud) };
 	let ev = unsafe { &*ev };
@@ -214,7 +414,8 @@ extern fn on_touch_down(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) ->
 	for i in 0..ev.num_touches {
 		let touch = &ev.touches[i as usize];
 		if touch.is_changed > 0 {
-			ctx.on_touch_down(touch.id as u32, Vec2i::new(touch.x, touch.y));
+			let pos = ctx.viewport.client_to_pixel(Vec2i::new(touch.x, touch.y));
+			ctx.on_touch_down(touch.id as u32, pos);
 		}
 	}
 
@@ -244,7 +445,8 @@ extern fn on_touch_move(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) ->
 	for i in 0..ev.num_touches {
 		let touch = &ev.touches[i as usize];
 		if touch.is_changed > 0 {
-			ctx.on_touch_move(touch.id as u32, Vec2i::new(touch.x, touch.y));
+			let pos = ctx.viewport.client_to_pixel(Vec2i::new(touch.x, touch.y));
+			ctx.on_touch_move(touch.id as u32, pos);
 		}
 	}
 
@@ -252,13 +454,25 @@ extern fn on_touch_move(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) ->
 }
 
 
+extern fn on_key_down(_: i32, ev: *const EmscriptenKeyboardEvent, ud: *mut u8) -> i32 {
+	let ctx: &mut MainContext = unsafe{ transmute(ud) };
+	let ev = unsafe { &*ev };
+
+	if ev.repeat != 0 { return 0 }
+
+	ctx.on_key_down(ev.key_code);
+
+	0
+}
+
 extern fn on_mouse_down(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
 	let ctx: &mut MainContext = unsafe{ transmute(ud) };
 	let ev = unsafe { &*ev };
 
 	if ctx.is_touch_input { return 0 }
 
-	ctx.on_touch_down(0, Vec2i::new(ev.x, ev.y));
+	let pos = ctx.viewport.client_to_pixel(Vec2i::new(ev.x, ev.y));
+	ctx.on_touch_down(0, pos);
 
 	0
 }
@@ -280,7 +494,8 @@ extern fn on_mouse_move(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) ->
 
 	if ctx.is_touch_input { return 0 }
 
-	ctx.on_touch_move(0, Vec2i::new(ev.x, ev.y));
+	let pos = ctx.viewport.client_to_pixel(Vec2i::new(ev.x, ev.y));
+	ctx.on_touch_move(0, pos);
 
 	0
 }
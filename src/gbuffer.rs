@@ -0,0 +1,129 @@
+use common::*;
+
+use rendering::gl;
+use rendering::capabilities;
+use rendering::texture::Texture;
+use rendering::format::ColorFormat;
+use rendering::framebuffer::{Framebuffer, FramebufferBuilder, FramebufferError};
+use rendering::render_graph::Target;
+
+use std::any::Any;
+
+// Target layout, must match crystal.frag
+pub const FRONT_NORMAL: usize = 0;
+pub const BACK_NORMAL: usize = 1;
+pub const FRONT_DEPTH: usize = 2;
+pub const BACK_DEPTH: usize = 3;
+
+pub const TARGET_COUNT: usize = 4;
+
+// Prepended to crystal.frag when all targets can be written in one pass
+pub const MRT_SHADER_PREAMBLE: &'static str = "#define GBUFFER_MRT\n";
+
+// Front and back face normals, linear depth and material ids of the crystal. With draw buffers
+//	support all targets are attached to one framebuffer and written in a single draw, otherwise
+//	each target gets its own framebuffer and the mesh is drawn once per target
+pub struct GBuffer {
+	framebuffers: Vec<Framebuffer>,
+}
+
+impl GBuffer {
+	pub fn new(allow_mrt: bool) -> Self {
+		let caps = capabilities::get();
+
+		if allow_mrt && caps.max_draw_buffers >= TARGET_COUNT as u32 {
+			let mrt = GBuffer::build_mrt(ColorFormat::Rgba16F)
+				.or_else(|_| GBuffer::build_mrt(ColorFormat::Rgba8));
+
+			match mrt {
+				Ok(fb) => return GBuffer::from_framebuffers(vec![fb]),
+				Err(err) => println!("{}, falling back to one pass per G-buffer target", err),
+			}
+		}
+
+		let framebuffers = (0..TARGET_COUNT)
+			.map(|_| GBuffer::build_single(ColorFormat::Rgba16F)
+				.or_else(|_| GBuffer::build_single(ColorFormat::Rgba8))
+				.expect("Failed to create G-buffer target"))
+			.collect();
+
+		GBuffer::from_framebuffers(framebuffers)
+	}
+
+	fn build_mrt(format: ColorFormat) -> Result<Framebuffer, FramebufferError> {
+		(0..TARGET_COUNT)
+			.fold(FramebufferBuilder::new_unsized(), |b, _| b.add_target_with_format(format))
+			.finalize()
+	}
+
+	fn build_single(format: ColorFormat) -> Result<Framebuffer, FramebufferError> {
+		FramebufferBuilder::new_unsized()
+			.add_target_with_format(format)
+			.finalize()
+	}
+
+	// Depth is packed across channels, so targets must never be filtered
+	fn from_framebuffers(mut framebuffers: Vec<Framebuffer>) -> Self {
+		for fb in framebuffers.iter_mut() {
+			for i in 0..fb.target_count() {
+				let tex = fb.get_target(i).unwrap();
+				let _guard = tex.bind_guard();
+
+				unsafe {
+					gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+					gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+				}
+			}
+		}
+
+		GBuffer { framebuffers }
+	}
+
+	pub fn is_mrt(&self) -> bool {
+		self.framebuffers.len() == 1
+	}
+
+	pub fn shader_preamble(&self) -> &'static str {
+		if self.is_mrt() { MRT_SHADER_PREAMBLE } else { "" }
+	}
+
+	pub fn get_target(&mut self, id: usize) -> &mut Texture {
+		assert!(id < TARGET_COUNT);
+
+		if self.is_mrt() {
+			self.framebuffers[0].get_target(id).unwrap()
+		} else {
+			self.framebuffers[id].get_target(0).unwrap()
+		}
+	}
+
+	// Clears the G-buffer and calls draw for each pass needed to fill it.
+	//	draw is passed the target to output when drawing one target at a time
+	pub fn render<F>(&self, mut draw: F) where F: FnMut(Option<usize>) {
+		let passes = self.framebuffers.iter().enumerate()
+			.map(|(i, fb)| (fb, if self.is_mrt() { None } else { Some(i) }));
+
+		for (fb, output) in passes {
+			fb.bind();
+
+			unsafe {
+				gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+				gl::Clear(gl::COLOR_BUFFER_BIT);
+			}
+
+			draw(output);
+		}
+
+		Framebuffer::unbind();
+	}
+}
+
+impl Target for GBuffer {
+	fn resize(&mut self, size: Vec2i) {
+		for fb in self.framebuffers.iter_mut() {
+			fb.resize(size);
+		}
+	}
+
+	fn as_any_mut(&mut self) -> &mut Any { self }
+}
@@ -0,0 +1,88 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use ems;
+
+const POLL_INTERVAL: f64 = 1.0;
+
+// Periodically refetches shader sources from the dev server, and keeps track of which ones
+//	have changed since they were last taken. Only meant for development builds
+pub struct ShaderReloader {
+	sources: Vec<(&'static str, String)>,
+	changed: Vec<&'static str>,
+
+	fetched: Rc<RefCell<Vec<(usize, String)>>>,
+	in_flight: Rc<Cell<usize>>,
+
+	poll_timer: f64,
+}
+
+impl ShaderReloader {
+	pub fn new(assets: &[(&'static str, &'static str)]) -> Self {
+		ShaderReloader {
+			sources: assets.iter()
+				.map(|&(path, src)| (path, src.to_string()))
+				.collect(),
+
+			changed: Vec::new(),
+
+			fetched: Rc::new(RefCell::new(Vec::new())),
+			in_flight: Rc::new(Cell::new(0)),
+
+			poll_timer: POLL_INTERVAL,
+		}
+	}
+
+	pub fn update(&mut self, dt: f64) {
+		for (idx, src) in self.fetched.borrow_mut().drain(..) {
+			let entry = &mut self.sources[idx];
+			if entry.1 == src { continue }
+
+			entry.1 = src;
+			if !self.changed.contains(&entry.0) {
+				self.changed.push(entry.0);
+			}
+		}
+
+		self.poll_timer -= dt;
+		if self.poll_timer > 0.0 || self.in_flight.get() > 0 { return }
+
+		self.poll_timer = POLL_INTERVAL;
+
+		for (idx, &(path, _)) in self.sources.iter().enumerate() {
+			let fetched = self.fetched.clone();
+			let in_flight = self.in_flight.clone();
+
+			in_flight.set(in_flight.get() + 1);
+
+			let url = format!("{}/assets/{}", env!("CARGO_PKG_NAME"), path);
+			ems::fetch_async(&url, move |data| {
+				in_flight.set(in_flight.get() - 1);
+
+				if let Some(data) = data {
+					let src = String::from_utf8_lossy(data).into_owned();
+					fetched.borrow_mut().push((idx, src));
+				}
+			});
+		}
+	}
+
+	// Makes every shader count as changed, so that programs built from the original sources
+	//	can be brought back up to date
+	pub fn mark_all_changed(&mut self) {
+		self.changed = self.sources.iter()
+			.map(|&(path, _)| path)
+			.collect();
+	}
+
+	pub fn take_changed(&mut self) -> Vec<&'static str> {
+		::std::mem::replace(&mut self.changed, Vec::new())
+	}
+
+	pub fn get_source(&self, path: &str) -> &str {
+		self.sources.iter()
+			.find(|&&(p, _)| p == path)
+			.map(|&(_, ref src)| src.as_str())
+			.expect("Unknown shader asset")
+	}
+}
@@ -9,16 +9,31 @@ extern crate common;
 pub use common::*;
 
 mod resources;
-mod rendering;
+#[macro_use] mod rendering;
 mod crystal;
+mod gbuffer;
+mod postprocess;
+mod quality;
+mod clock;
+mod trackball;
+mod png;
 
 #[macro_use] mod ems;
-
-use std::time;
+mod hot_reload;
 
 use rendering::mesh_builder::{MeshBuilder, Mesh};
-use rendering::framebuffer::{Framebuffer, FramebufferBuilder};
+use rendering::framebuffer::{self, Framebuffer, FramebufferBuilder};
+use rendering::format::ColorFormat;
+use rendering::vertex::{VertexAttribute, VertexLayout};
 use rendering::*;
+use hot_reload::ShaderReloader;
+use rendering::render_graph::{RenderGraph, Targets, TargetId, TargetRef, PingPong};
+use gbuffer::GBuffer;
+use crystal::Crystal;
+use postprocess::{PostStack, PostShaders, PostSettings};
+use quality::QualityManager;
+use clock::{Clock, FixedTimestep};
+use trackball::{Trackball, TrackballSettings};
 
 pub use resources::*;
 
@@ -29,14 +44,23 @@ pub fn rand_f32(range: f32) -> f32 {
 	f * range
 }
 
-pub fn rand_vec2() -> Vec2 {
-	Vec2::new(rand_f32(2.0) - 1.0, rand_f32(2.0) - 1.0)
-}
-
 pub fn rand_vec3() -> Vec3 {
 	Vec3::new(rand_f32(2.0) - 1.0, rand_f32(2.0) - 1.0, rand_f32(2.0) - 1.0)
 }
 
+pub fn quat_to_vec4(q: Quat) -> Vec4 {
+	Vec4::new(q.x, q.y, q.z, q.w)
+}
+
+// Normalized lerp along the shorter arc, close enough to slerp for the small steps it's used for
+pub fn quat_nlerp(a: Quat, b: Quat, t: f32) -> Quat {
+	let dot = a.x*b.x + a.y*b.y + a.z*b.z + a.w*b.w;
+	let sign = if dot < 0.0 { -1.0 } else { 1.0 };
+	let lerp = |from: f32, to: f32| from + (to * sign - from) * t;
+
+	Quat::from_raw(lerp(a.x, b.x), lerp(a.y, b.y), lerp(a.z, b.z), lerp(a.w, b.w)).normalize()
+}
+
 
 fn main() {
 	use std::mem::uninitialized;
@@ -77,50 +101,232 @@ fn main() {
 		panic!("Failed to make webgl context current");
 	}
 
+	rendering::capabilities::init(ems_context_handle);
+
 	// js!{ b"document.addEventListener('contextmenu', function(e) { e.preventDefault(); return false; })\0" };
 
 	let ctx = MainContext::new();
 	ems::register_callbacks(Box::into_raw(box ctx));
 }
 
-pub struct MainContext {
-	viewport: Viewport,
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct StarVertex {
+	pos: Vec3,
+	color: f32,
+	point_size: f32,
+}
+
+impl VertexLayout for StarVertex {
+	fn attributes() -> &'static [VertexAttribute] {
+		static ATTRIBUTES: &'static [VertexAttribute] = &[
+			VertexAttribute::float("position", 3),
+			VertexAttribute::float("color", 1),
+			VertexAttribute::float("point_size", 1),
+		];
+
+		ATTRIBUTES
+	}
+}
+
+// Each star is expanded into a quad covering its path over the frame, see star_streak.vert
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct StarStreakVertex {
+	star: StarVertex,
+	corner: Vec2,
+}
+
+impl VertexLayout for StarStreakVertex {
+	fn attributes() -> &'static [VertexAttribute] {
+		static ATTRIBUTES: &'static [VertexAttribute] = &[
+			VertexAttribute::float("position", 3),
+			VertexAttribute::float("color", 1),
+			VertexAttribute::float("point_size", 1),
+			VertexAttribute::float("uv", 2),
+		];
+
+		ATTRIBUTES
+	}
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct MotionBlurStep(f32);
+
+impl VertexLayout for MotionBlurStep {
+	fn attributes() -> &'static [VertexAttribute] {
+		static ATTRIBUTES: &'static [VertexAttribute] = &[
+			VertexAttribute::float("motion_blur_step", 1),
+		];
+
+		ATTRIBUTES
+	}
+}
+
+// Longer frames are simulated as if they took this long, so that a stall doesn't send
+//	the crystal spinning
+const MAX_FRAME_TIME: f64 = 0.1;
+
+// Rotation and momentum are simulated at this rate, independent of display refresh rate
+const SIMULATION_STEP: f64 = 1.0 / 60.0;
+
+// Backstop for MAX_FRAME_TIME, which already keeps frames to 6 steps
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+// Caps the canvas resolution on very dense screens, where the extra pixels cost more than they show
+const MAX_PIXEL_RATIO: f32 = 2.0;
+
+// Prepended to crystal.frag when normals can be derived from screen space derivatives
+const DERIVED_NORMALS_PREAMBLE: &'static str = "#define DERIVED_NORMALS\n";
+
+// Each instance of the star mesh is drawn at one step between the previous and current rotation
+fn upload_motion_blur_steps(star_mesh: &mut Mesh, steps: u32) {
+	let steps = steps.max(1);
+	let star_steps = (0..=steps)
+		.map(|i| MotionBlurStep(i as f32 / steps as f32))
+		.collect::<Vec<_>>();
+
+	star_mesh.upload_instances(&star_steps);
+}
+
+// Enough stars for the highest quality level, lower levels draw a prefix of them
+fn generate_stars() -> Vec<StarVertex> {
+	(0..500).map(|_| {
+		let x = rand_f32(1.0);
+
+		let dist = (1.0 - x) * 190.0 + 15.0;
+		let color = x * 0.3 + 0.02;
+		let point_size = x * 5.0 + 1.0;
+
+		StarVertex {
+			pos: rand_vec3().normalize() * dist,
+			color,
+			point_size,
+		}
+	}).collect()
+}
+
+uniform_block! {
+	pub struct StarComposeUniforms {
+		inv_proj: Mat4 = "inv_proj",
+		refractive_index: f32 = "u_refractive_index",
+		time: f32 = "u_time",
+		march_steps: f32 = "u_march_steps",
+
+		front_normal: i32 = "u_front_normal",
+		back_normal: i32 = "u_back_normal",
+		front_depth: i32 = "u_front_depth",
+		back_depth: i32 = "u_back_depth",
+		bgcolor: i32 = "u_bgcolor",
+
+		star_cubemap: i32 = "u_star_cubemap",
+		use_star_cubemap: i32 = "u_use_star_cubemap",
+		inv_view_rotation: Mat4 = "u_inv_view_rotation",
+	}
+}
+
+// Camera state for the frame being rendered, shared between render passes
+struct FrameParams {
+	// Size of the render targets, which the viewport is scaled down to at lower quality levels
+	render_size: Vec2i,
+
+	proj_mat: Mat4,
+	trans_mat: Mat4,
+	view_mat: Mat4,
+	view_proj: Mat4,
+
+	prev_rotation: Quat,
+	rotation: Quat,
+}
+
+struct FrameTargets {
+	star: TargetId<Framebuffer>,
+	crystal_gbuffer: TargetId<GBuffer>,
+	crystal_lines: TargetId<PingPong>,
+	post: TargetId<PostStack>,
+}
+
+// Everything that lives in the GL context. Kept apart from the rest of MainContext so that
+//	it can be rebuilt as a whole when the context is lost, see on_context_restored
+struct GpuResources {
 	shader_fb: Shader,
 	shader_star: Shader,
+	shader_star_streak: Shader,
 	shader_color: Shader,
 	shader_crystal: Shader,
 	shader_line_fuzz: Shader,
 	shader_star_compose: Shader,
-	prev_frame: time::Instant,
-	time: f64,
+	post_shaders: PostShaders,
 
-	cmbuilder: MeshBuilder,
 	crystal_mesh: Mesh,
 	crystal_mesh_lines: Mesh,
-	crystal_targets: [Framebuffer; 2],
-	crystal_refract_idx: f32,
-	crystal_line_targets: [Framebuffer; 2],
-	target_flip: usize,
+	crystal_shader_preamble: String,
+
+	// Whether crystal.frag derives normals itself, letting faces share vertices
+	crystal_derived_normals: bool,
 
 	quad_mesh: Mesh,
 	star_mesh: Mesh,
-	star_target: Framebuffer,
+	star_streak_mesh: Mesh,
 
-	rotation: Quat,
+	// Only None while the graph is executing, since passes need the rest of MainContext
+	render_graph: Option<RenderGraph<MainContext>>,
+	targets: FrameTargets,
+
+	// Rendered once on first use, and sampled by the compose pass instead of the star target
+	star_cubemap: Option<CubeTexture>,
+}
+
+pub struct MainContext {
+	viewport: Viewport,
+	gpu: GpuResources,
+	shader_reloader: Option<ShaderReloader>,
+	clock: Clock,
+	timestep: FixedTimestep,
+	time: f64,
+
+	// Kept so that the same star field can be uploaded again after a context loss
+	stars: Vec<StarVertex>,
+
+	cmbuilder: MeshBuilder,
+	crystal: Option<Crystal>,
+	crystal_refract_idx: f32,
+
+	frame: FrameParams,
+
+	post_settings: PostSettings,
+	quality: QualityManager,
+
+	use_star_cubemap: bool,
+
+	// Toggles between star_streak_mesh and the instanced star_mesh path for comparison
+	use_star_streaks: bool,
+
+	// Set by the screenshot key, and handled at the end of the next frame
+	screenshot_requested: bool,
+
+	// Owns the simulated rotation. prev_rotation is its rotation a step earlier, and
+	//	render_rotation is the interpolated rotation last rendered
+	trackball: Trackball,
+	prev_rotation: Quat,
+	render_rotation: Quat,
 
 	touch_id: Option<u32>,
 	touch_start: Vec2i,
-	touch_prev: Vec2i,
-
-	touch_delta: Vec2,
 
 	// ems info
 	is_touch_input: bool,
 	is_potential_tap: bool,
+
+	// Set between the webglcontextlost and webglcontextrestored events, while every GL
+	//	handle is invalid
+	context_lost: bool,
 }
 
-impl MainContext {
-	fn new() -> Self {
+impl GpuResources {
+	// Star meshes are built from stars, the crystal meshes start out empty
+	fn new(stars: &[StarVertex]) -> Self {
 		unsafe {
 			gl::Enable(gl::DEPTH_TEST);
 			gl::Enable(gl::CULL_FACE);
@@ -133,28 +339,27 @@ impl MainContext {
 		let mut star_mesh = Mesh::new();
 		let mut star_builder = MeshBuilder::new();
 
-		for _ in 0..500 {
-			use rendering::mesh_builder::Vertex;
-
-			let x = rand_f32(1.0);
+		let mut star_streak_mesh = Mesh::new();
+		let mut star_streak_builder = MeshBuilder::new();
 
-			let dist = (1.0 - x) * 190.0 + 15.0;
-			let color = x * 0.3 + 0.02;
-			let point_size = x * 5.0 + 1.0;
-
-			let info = Vec3::new(color, point_size, 0.0);
-
-			star_builder.add_vert(Vertex::new_normal(rand_vec3().normalize() * dist, info));
+		for &star in stars {
+			star_builder.add_vert(star);
+			star_streak_builder.add_quad(&[
+				StarStreakVertex { star, corner: Vec2::new(0.0,-1.0) },
+				StarStreakVertex { star, corner: Vec2::new(1.0,-1.0) },
+				StarStreakVertex { star, corner: Vec2::new(1.0, 1.0) },
+				StarStreakVertex { star, corner: Vec2::new(0.0, 1.0) },
+			]);
 		}
 
 		star_builder.upload_to(&mut star_mesh);
+		star_streak_builder.upload_to(&mut star_streak_mesh);
 
 		let mut quad_mesh = Mesh::new();
-		let mut quad_builder = star_builder;
+		let mut quad_builder = MeshBuilder::new();
 
 		{	use rendering::mesh_builder::Vertex;
 
-			quad_builder.clear();
 			quad_builder.add_quad(&[
 				Vertex::new(Vec3::new(-1.0,-1.0, 0.0)),
 				Vertex::new(Vec3::new( 1.0,-1.0, 0.0)),
@@ -167,234 +372,544 @@ impl MainContext {
 
 		let mut star_target = FramebufferBuilder::new_unsized()
 			.add_target()
-			.finalize();
+			.finalize()
+			.expect("Failed to create star target");
 
 		star_target.get_target(0).unwrap().nearest();
 
-		let crystal_targets = [
-			FramebufferBuilder::new_unsized()
-				.add_target()
-				.add_depth()
-				.finalize(),
+		// Draw buffers aren't usable from GLSL ES 1.0 shaders on every context,
+		//	so fall back to one pass per target if the MRT shader doesn't compile
+		let crystal_derived_normals = rendering::capabilities::get().standard_derivatives;
+		let normals_preamble = if crystal_derived_normals { DERIVED_NORMALS_PREAMBLE } else { "" };
 
-			FramebufferBuilder::new_unsized()
-				.add_target()
-				.add_depth()
-				.finalize(),
-		];
+		let mrt_gbuffer = GBuffer::new(true);
+		let mrt_preamble = format!("{}{}", mrt_gbuffer.shader_preamble(), normals_preamble);
+		let mrt_crystal_frag = format!("{}{}", mrt_preamble, CRYSTAL_SHADER_FRAG_SRC);
 
-		let crystal_line_targets = [
+		let (crystal_gbuffer, shader_crystal, crystal_shader_preamble) = match Shader::try_new(CRYSTAL_SHADER_VERT_SRC, &mrt_crystal_frag) {
+			Ok(shader) => (mrt_gbuffer, shader, mrt_preamble),
+			Err(log) => {
+				println!("Failed to compile G-buffer shader, falling back to one pass per target:\n{}", log);
+
+				let crystal_frag = format!("{}{}", normals_preamble, CRYSTAL_SHADER_FRAG_SRC);
+				(GBuffer::new(false), Shader::new(CRYSTAL_SHADER_VERT_SRC, &crystal_frag), normals_preamble.to_string())
+			}
+		};
+
+		let crystal_lines = PingPong::new(
 			FramebufferBuilder::new_unsized()
 				.add_target()
-				.finalize(),
+				.finalize()
+				.expect("Failed to create crystal line target"),
 
 			FramebufferBuilder::new_unsized()
 				.add_target()
-				.finalize(),
-		];
+				.finalize()
+				.expect("Failed to create crystal line target"),
+		);
 
-		MainContext {
-			viewport: Viewport::new(),
+		let mut render_graph = RenderGraph::new();
+
+		let targets = FrameTargets {
+			star: render_graph.add_target("star", star_target),
+			crystal_gbuffer: render_graph.add_target("crystal_gbuffer", crystal_gbuffer),
+			crystal_lines: render_graph.add_target("crystal_lines", crystal_lines),
+			post: render_graph.add_target("post", PostStack::new()),
+		};
+
+		MainContext::build_render_graph(&mut render_graph, &targets);
+
+		GpuResources {
 			shader_fb: Shader::new(FB_SHADER_VERT_SRC, FB_SHADER_FRAG_SRC),
 			shader_star: Shader::new(STAR_SHADER_VERT_SRC, STAR_SHADER_FRAG_SRC),
+			shader_star_streak: Shader::new(STAR_STREAK_SHADER_VERT_SRC, STAR_STREAK_SHADER_FRAG_SRC),
 			shader_color: Shader::new(BASIC_TRANSFORM_SHADER_VERT_SRC, COLOR_SHADER_FRAG_SRC),
-			shader_crystal: Shader::new(CRYSTAL_SHADER_VERT_SRC, CRYSTAL_SHADER_FRAG_SRC),
+			shader_crystal,
 			shader_line_fuzz: Shader::new(FB_SHADER_VERT_SRC, LINE_FUZZ_SHADER_FRAG_SRC),
 			shader_star_compose: Shader::new(FB_SHADER_VERT_SRC, STAR_COMPOSE_SHADER_FRAG_SRC),
-			prev_frame: time::Instant::now(),
-			time: 0.0,
+			post_shaders: PostShaders::new(),
 
-			cmbuilder: MeshBuilder::new(),
 			crystal_mesh: Mesh::new(),
 			crystal_mesh_lines: Mesh::new(),
-			crystal_targets,
-			crystal_refract_idx: 1.0,
-			crystal_line_targets,
-			target_flip: 0,
+			crystal_shader_preamble,
+			crystal_derived_normals,
 
 			quad_mesh,
 			star_mesh,
-			star_target,
+			star_streak_mesh,
+
+			render_graph: Some(render_graph),
+			targets,
 
-			rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
+			star_cubemap: None,
+		}
+	}
+}
+
+impl MainContext {
+	fn new() -> Self {
+		let stars = generate_stars();
+		let gpu = GpuResources::new(&stars);
+
+		let mut ctx = MainContext {
+			viewport: Viewport::new(),
+			gpu,
+			shader_reloader: if cfg!(debug_assertions) { Some(ShaderReloader::new(SHADER_ASSETS)) } else { None },
+			clock: Clock::new(),
+			timestep: FixedTimestep::new(SIMULATION_STEP, MAX_STEPS_PER_FRAME),
+			time: 0.0,
+
+			stars,
+
+			cmbuilder: MeshBuilder::new(),
+			crystal: None,
+			crystal_refract_idx: 1.0,
+
+			frame: FrameParams {
+				render_size: Vec2i::zero(),
+
+				proj_mat: Mat4::ident(),
+				trans_mat: Mat4::ident(),
+				view_mat: Mat4::ident(),
+				view_proj: Mat4::ident(),
+
+				prev_rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
+				rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
+			},
+
+			post_settings: PostSettings::default(),
+			quality: QualityManager::new(60.0),
+
+			use_star_cubemap: false,
+
+			use_star_streaks: true,
+			screenshot_requested: false,
+
+			trackball: Trackball::new(TrackballSettings::default()),
+			prev_rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
+			render_rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
 
 			touch_id: None,
-			
 			touch_start: Vec2i::zero(),
-			touch_prev: Vec2i::zero(),
-			touch_delta: Vec2::zero(),
 
 			is_touch_input: false,
 			is_potential_tap: false,
-		}
+
+			context_lost: false,
+		};
+
+		upload_motion_blur_steps(&mut ctx.gpu.star_mesh, ctx.quality.current().motion_blur_steps);
+
+		ctx.on_resize();
+		ctx
 	}
 
 	fn on_update(&mut self) {
-		let now = time::Instant::now();
-		let diff = now - self.prev_frame;
-		self.prev_frame = now;
-
-		let udt = diff.subsec_nanos() / 1000;
-		let dt = udt as f64 / 1000_000.0;
+		let frame_time = clock::duration_to_secs(self.clock.tick());
+		let dt = frame_time.min(MAX_FRAME_TIME);
 
 		self.time += dt;
 
-		if self.touch_id.is_none() && self.touch_delta.length() < 0.0005 {
-			self.touch_delta = self.touch_delta + rand_vec2() * 0.0001;
-
-			// Sustain momentum
-			let dir = self.touch_delta.normalize();
-			if dir.x.is_finite() && dir.y.is_finite() {
-				self.touch_delta = self.touch_delta + dir * 0.0002;
-			}
+		if let Some(ref mut reloader) = self.shader_reloader {
+			reloader.update(dt);
 		}
 
-		if self.touch_id.is_none() {
-			self.touch_delta = self.touch_delta * (1.0 - dt as f32 * 0.3);
+		self.reload_shaders();
+
+		// Stalls should still count against quality, so this sees the unclamped frame time
+		if self.quality.update(frame_time) {
+			self.apply_quality();
 		}
 
-		self.fit_canvas();
-		self.crystal_targets[0].resize(self.viewport.size);
-		self.crystal_targets[1].resize(self.viewport.size);
-		self.crystal_line_targets[0].resize(self.viewport.size);
-		self.crystal_line_targets[1].resize(self.viewport.size);
-		self.star_target.resize(self.viewport.size);
+		for _ in 0..self.timestep.advance(dt) {
+			self.step_simulation(SIMULATION_STEP);
+		}
 
-		if self.crystal_mesh.count < 3 {
+		if self.gpu.crystal_mesh.count < 3 {
 			self.build_crystal();
 		}
 	}
 
+	fn step_simulation(&mut self, step: f64) {
+		self.prev_rotation = self.trackball.rotation;
+		self.trackball.update(step);
+	}
+
+	fn build_render_graph(graph: &mut RenderGraph<MainContext>, targets: &FrameTargets) {
+		let star = TargetRef::current(targets.star);
+		let gbuffer = TargetRef::current(targets.crystal_gbuffer);
+		let lines = TargetRef::current(targets.crystal_lines);
+		let prev_lines = TargetRef::previous(targets.crystal_lines);
+		let scene = TargetRef::current(targets.post);
+
+		graph.add_pass("stars", &[], &[star], MainContext::render_stars);
+		graph.add_pass("crystal_gbuffer", &[], &[gbuffer], MainContext::render_crystal_gbuffer);
+		graph.add_pass("star_compose", &[star, gbuffer], &[scene], MainContext::render_star_compose);
+
+		// Lines are drawn over last frame's fuzzed lines, then fuzzed again into this frame's target
+		graph.add_pass("crystal_lines", &[], &[prev_lines], MainContext::render_crystal_lines);
+		graph.add_pass("line_fuzz", &[prev_lines], &[lines], MainContext::render_line_fuzz);
+		graph.add_pass("line_present", &[lines], &[scene], MainContext::render_line_present);
+
+		graph.add_pass("post_process", &[scene], &[TargetRef::Screen], MainContext::render_post_process);
+	}
+
 	fn on_render(&mut self) {
+		let render_size = self.render_size();
+
 		unsafe {
 			let g = 0.03;
 			gl::ClearColor(g, g, g, 1.0);
 			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-			let Vec2i{x: vw, y: vh} = self.viewport.size;
-			gl::Viewport(0, 0, vw, vh);
+			gl::Viewport(0, 0, render_size.x, render_size.y);
+		}
 
-			let rot_diff = Quat::new(Vec3::new(0.0, 1.0, 0.0), -self.touch_delta.x * PI / 2.0)
-				* Quat::new(Vec3::new(1.0, 0.0, 0.0), -self.touch_delta.y * PI / 2.0);
+		let alpha = self.timestep.interpolation_factor();
+		let new_rotation = quat_nlerp(self.prev_rotation, self.trackball.rotation, alpha);
 
-			let new_rotation = (self.rotation * rot_diff).normalize();
+		let proj_mat = Mat4::perspective(PI/3.0, self.viewport.get_aspect(), 0.005, 1000.0);
+		let trans_mat = Mat4::translate(Vec3::new(0.0, 0.0,-2.0));
+		let view_mat = trans_mat * new_rotation.conjugate().to_mat4();
+		let view_proj = proj_mat * view_mat;
 
-			let proj_mat = Mat4::perspective(PI/3.0, self.viewport.get_aspect(), 0.005, 1000.0);
-			let trans_mat = Mat4::translate(Vec3::new(0.0, 0.0,-2.0));
+		self.frame = FrameParams {
+			render_size,
+			proj_mat, trans_mat, view_mat, view_proj,
 
-			gl::EnableVertexAttribArray(0);
-			gl::EnableVertexAttribArray(1);
-			
-			self.star_target.bind();
-			self.shader_color.use_program();
-			self.shader_color.set_proj(&Mat4::ident());
-			self.shader_color.set_uniform_vec4("u_color", &Vec4::new(0.0, 0.0, 0.0, 0.15));
-			self.quad_mesh.bind();
-			self.quad_mesh.draw(gl::TRIANGLES);
+			prev_rotation: self.render_rotation,
+			rotation: new_rotation,
+		};
 
-			self.shader_star.use_program();
-			self.shader_star.set_uniform_f32("u_time", self.time as f32);
+		self.render_rotation = new_rotation;
 
-			let max_star_steps = 100u32;
+		if self.use_star_cubemap && self.gpu.star_cubemap.is_none() {
+			self.render_star_cubemap();
+			unsafe { gl::Viewport(0, 0, render_size.x, render_size.y); }
+		}
 
-			for i in 0..=max_star_steps {
-				let a = i as f32 / max_star_steps as f32;
-				// let rotation = (self.rotation * (1.0 - a) + new_rotation * a).normalize();
-				let rotation = a.ease_linear(self.rotation, new_rotation).normalize();
+		let mut graph = self.gpu.render_graph.take().unwrap();
+		graph.execute(self);
+		self.gpu.render_graph = Some(graph);
 
-				let view_mat = trans_mat * rotation.conjugate().to_mat4();
-				let view_proj = proj_mat * view_mat;
+		if self.screenshot_requested {
+			self.screenshot_requested = false;
+			self.save_screenshot();
+		}
+	}
 
-				self.shader_star.set_proj(&view_proj);
-				self.star_mesh.bind();
-				self.star_mesh.draw(gl::POINTS);
+	fn render_stars(&mut self, targets: &mut Targets) {
+		let frame = &self.frame;
+		let quality = self.quality.current();
+
+		targets.get(self.gpu.targets.star).bind();
+
+		self.gpu.shader_color.use_program();
+		self.gpu.shader_color.set_proj(&Mat4::ident());
+		self.gpu.shader_color.set_uniform_vec4("u_color", &Vec4::new(0.0, 0.0, 0.0, 0.15));
+		self.gpu.quad_mesh.bind();
+		self.gpu.quad_mesh.draw(gl::TRIANGLES);
+
+		if self.use_star_streaks {
+			self.gpu.shader_star_streak.use_program();
+			self.gpu.shader_star_streak.set_uniform_f32("u_time", self.time as f32);
+			self.gpu.shader_star_streak.set_proj(&frame.proj_mat);
+			self.gpu.shader_star_streak.set_view(&frame.trans_mat);
+			self.gpu.shader_star_streak.set_uniform_vec4("u_rotation_from", &quat_to_vec4(frame.prev_rotation));
+			self.gpu.shader_star_streak.set_uniform_vec4("u_rotation_to", &quat_to_vec4(frame.rotation));
+			self.gpu.shader_star_streak.set_uniform_vec2("u_viewport_size", &frame.render_size.to_vec2());
+			self.gpu.shader_star_streak.set_uniform_f32("u_resolution_scale", quality.resolution_scale);
+			self.gpu.star_streak_mesh.bind();
+
+			// Two triangles per star
+			self.gpu.star_streak_mesh.draw_partial(gl::TRIANGLES, quality.star_count * 6);
+
+		} else {
+			// Each instance of the star mesh is drawn at a rotation between the previous and current frame
+			self.gpu.shader_star.use_program();
+			self.gpu.shader_star.set_uniform_f32("u_time", self.time as f32);
+			self.gpu.shader_star.set_proj(&frame.proj_mat);
+			self.gpu.shader_star.set_view(&frame.trans_mat);
+			self.gpu.shader_star.set_uniform_vec4("u_rotation_from", &quat_to_vec4(frame.prev_rotation));
+			self.gpu.shader_star.set_uniform_vec4("u_rotation_to", &quat_to_vec4(frame.rotation));
+			self.gpu.shader_star.set_uniform_f32("u_resolution_scale", quality.resolution_scale);
+			self.gpu.star_mesh.bind();
+			self.gpu.star_mesh.draw_instanced_partial(gl::POINTS, quality.star_count);
+		}
+
+		Framebuffer::unbind();
+	}
+
+	fn render_crystal_gbuffer(&mut self, targets: &mut Targets) {
+		let shader_crystal = &self.gpu.shader_crystal;
+		let crystal_mesh = &self.gpu.crystal_mesh;
+
+		shader_crystal.use_program();
+		shader_crystal.set_proj(&self.frame.view_proj);
+		shader_crystal.set_view(&self.frame.view_mat);
+		shader_crystal.set_uniform_f32("u_material_id", 1.0);
+
+		crystal_mesh.bind();
+
+		// Both sides of the crystal are written in the same pass, see crystal.frag
+		unsafe {
+			gl::Disable(gl::DEPTH_TEST);
+			gl::Disable(gl::CULL_FACE);
+		}
+
+		targets.get(self.gpu.targets.crystal_gbuffer).render(|output| {
+			if let Some(output) = output {
+				shader_crystal.set_uniform_i32("u_output", output as i32);
 			}
-			Framebuffer::unbind();
 
-			self.rotation = new_rotation;
+			crystal_mesh.draw(gl::TRIANGLES);
+		});
+
+		unsafe {
+			gl::Enable(gl::DEPTH_TEST);
+			gl::Enable(gl::CULL_FACE);
+		}
+	}
+
+	fn render_star_compose(&mut self, targets: &mut Targets) {
+		{
+			let gbuffer = targets.get(self.gpu.targets.crystal_gbuffer);
+			gbuffer.get_target(gbuffer::FRONT_NORMAL).bind_to_slot(0);
+			gbuffer.get_target(gbuffer::BACK_NORMAL).bind_to_slot(1);
+			gbuffer.get_target(gbuffer::FRONT_DEPTH).bind_to_slot(2);
+			gbuffer.get_target(gbuffer::BACK_DEPTH).bind_to_slot(3);
+		}
+
+		targets.get(self.gpu.targets.star).get_target(0).unwrap().bind_to_slot(4);
+		targets.get(self.gpu.targets.post).scene().bind();
+
+		let use_star_cubemap = match self.gpu.star_cubemap {
+			Some(ref cubemap) if self.use_star_cubemap => { cubemap.bind_to_slot(5); true }
+			_ => false
+		};
+
+		self.gpu.shader_star_compose.use_program();
+		self.gpu.shader_star_compose.set_proj(&self.frame.proj_mat);
+		self.gpu.shader_star_compose.set_uniforms(&StarComposeUniforms {
+			inv_proj: self.frame.proj_mat.inverse(),
+			refractive_index: self.crystal_refract_idx,
+			time: self.time as f32,
+			march_steps: self.quality.current().march_steps as f32,
+
+			front_normal: 0,
+			back_normal: 1,
+			front_depth: 2,
+			back_depth: 3,
+			bgcolor: 4,
+
+			star_cubemap: 5,
+			use_star_cubemap: use_star_cubemap as i32,
+			inv_view_rotation: self.frame.rotation.to_mat4(),
+		});
+		self.gpu.quad_mesh.bind();
+		self.gpu.quad_mesh.draw(gl::TRIANGLES);
+
+		Framebuffer::unbind();
+		Texture::unbind();
+	}
 
-			let view_mat = trans_mat * new_rotation.conjugate().to_mat4();
-			let view_proj = proj_mat * view_mat;
+	fn render_crystal_lines(&mut self, targets: &mut Targets) {
+		unsafe { gl::Disable(gl::DEPTH_TEST); }
 
-			self.shader_crystal.use_program();
-			self.shader_crystal.set_proj(&view_proj);
-			self.shader_crystal.set_view(&view_mat);
+		targets.get(self.gpu.targets.crystal_lines).previous().bind();
 
-			self.crystal_mesh.bind();
-			gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+		self.gpu.shader_color.use_program();
+		self.gpu.shader_color.set_proj(&self.frame.view_proj);
+		self.gpu.shader_color.set_uniform_vec4("u_color", &Vec4::new(0.9, 0.85, 0.87, 0.4));
+		self.gpu.crystal_mesh_lines.bind();
+		self.gpu.crystal_mesh_lines.draw(gl::LINES);
+		Framebuffer::unbind();
 
-			self.crystal_targets[0].bind();
-			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+		unsafe { gl::Enable(gl::DEPTH_TEST); }
+	}
 
-			gl::FrontFace(gl::CW);
-			self.shader_crystal.set_uniform_vec3("u_color", &Vec3::new(0.9, 0.0, 0.5));
-			self.crystal_mesh.draw(gl::TRIANGLES);
+	fn render_line_fuzz(&mut self, targets: &mut Targets) {
+		unsafe { gl::Disable(gl::DEPTH_TEST); }
 
-			self.crystal_targets[1].bind();
-			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+		self.gpu.shader_line_fuzz.use_program();
+		self.gpu.shader_line_fuzz.set_uniform_i32("u_color", 0);
+		self.gpu.shader_line_fuzz.set_uniform_f32("u_aspect", self.viewport.get_aspect());
+		self.gpu.shader_line_fuzz.set_uniform_f32("u_time", self.time as f32);
 
-			gl::FrontFace(gl::CCW);
-			self.shader_crystal.set_uniform_vec3("u_color", &Vec3::new(0.3, 0.0, 1.0));
-			self.crystal_mesh.draw(gl::TRIANGLES);
-
-			Framebuffer::unbind();
-
-			{
-				self.crystal_targets[0].get_target(0).unwrap().bind_to_slot(0);
-				self.crystal_targets[1].get_target(0).unwrap().bind_to_slot(1);
-				self.crystal_targets[0].get_depth().unwrap().bind_to_slot(2);
-				self.crystal_targets[1].get_depth().unwrap().bind_to_slot(3);
-				self.star_target.get_target(0).unwrap().bind_to_slot(4);
-
-				self.shader_star_compose.use_program();
-				self.shader_star_compose.set_proj(&proj_mat);
-				self.shader_star_compose.set_uniform_mat("inv_proj", &proj_mat.inverse());
-				self.shader_star_compose.set_uniform_f32("u_refractive_index", self.crystal_refract_idx);
-
-				self.shader_star_compose.set_uniform_i32("u_color0", 0);
-				self.shader_star_compose.set_uniform_i32("u_color1", 1);
-				self.shader_star_compose.set_uniform_i32("u_depth0", 2);
-				self.shader_star_compose.set_uniform_i32("u_depth1", 3);
-				self.shader_star_compose.set_uniform_i32("u_bgcolor", 4);
-				self.shader_star_compose.set_uniform_f32("u_time", self.time as f32);
-				self.quad_mesh.bind();
-				self.quad_mesh.draw(gl::TRIANGLES);
-
-				Texture::unbind();
+		let lines = targets.get(self.gpu.targets.crystal_lines);
+		lines.current().bind();
+		lines.previous().get_target(0).unwrap().bind_to_slot(0);
+
+		self.gpu.quad_mesh.bind();
+		self.gpu.quad_mesh.draw(gl::TRIANGLES);
+		Framebuffer::unbind();
+
+		unsafe { gl::Enable(gl::DEPTH_TEST); }
+	}
+
+	fn render_line_present(&mut self, targets: &mut Targets) {
+		unsafe { gl::Disable(gl::DEPTH_TEST); }
+
+		targets.get(self.gpu.targets.crystal_lines).current().get_target(0).unwrap().bind_to_slot(0);
+		targets.get(self.gpu.targets.post).scene().bind();
+
+		self.gpu.shader_fb.use_program();
+		self.gpu.quad_mesh.bind();
+		self.gpu.quad_mesh.draw(gl::TRIANGLES);
+		Mesh::unbind();
+		Framebuffer::unbind();
+
+		unsafe { gl::Enable(gl::DEPTH_TEST); }
+	}
+
+	fn render_post_process(&mut self, targets: &mut Targets) {
+		targets.get(self.gpu.targets.post)
+			.render(&self.gpu.post_shaders, &self.post_settings, &self.gpu.quad_mesh, self.viewport.size, self.time as f32);
+	}
+
+	// Renders the star field as seen from the center of the scene without any rotation.
+	//	Leaves the viewport covering the cube map
+	fn render_star_cubemap(&mut self) {
+		const CUBEMAP_SIZE: i32 = 1024;
+
+		let cubemap = CubeTexture::new(CUBEMAP_SIZE, ColorFormat::Rgba8);
+		let proj_mat = Mat4::perspective(PI/2.0, 1.0, 0.005, 1000.0);
+		let identity = Vec4::new(0.0, 0.0, 0.0, 1.0);
+
+		self.gpu.shader_star.use_program();
+		self.gpu.shader_star.set_uniform_f32("u_time", 0.0);
+		self.gpu.shader_star.set_proj(&proj_mat);
+		self.gpu.shader_star.set_uniform_vec4("u_rotation_from", &identity);
+		self.gpu.shader_star.set_uniform_vec4("u_rotation_to", &identity);
+		self.gpu.shader_star.set_uniform_f32("u_resolution_scale", 1.0);
+		self.gpu.star_mesh.bind();
+
+		let shader_star = &self.gpu.shader_star;
+		let star_mesh = &self.gpu.star_mesh;
+
+		let result = framebuffer::render_to_cube_faces(&cubemap, |face| {
+			shader_star.set_view(&face.view_rotation().to_mat4());
+
+			unsafe {
+				gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+				gl::Clear(gl::COLOR_BUFFER_BIT);
 			}
 
-			
-			gl::Disable(gl::DEPTH_TEST);
+			star_mesh.draw(gl::POINTS);
+		});
 
-			self.crystal_line_targets[self.target_flip].bind();
-			
-			self.shader_color.use_program();
-			self.shader_color.set_proj(&view_proj);
-			self.shader_color.set_uniform_vec4("u_color", &Vec4::new(0.9, 0.85, 0.87, 0.4));
-			self.crystal_mesh_lines.bind();
-			self.crystal_mesh_lines.draw(gl::LINES);
-			Framebuffer::unbind();
+		Mesh::unbind();
 
-			self.shader_line_fuzz.use_program();
-			self.shader_line_fuzz.set_uniform_i32("u_color", 0);
-			self.shader_line_fuzz.set_uniform_f32("u_aspect", self.viewport.get_aspect());
-			self.shader_line_fuzz.set_uniform_f32("u_time", self.time as f32);
+		match result {
+			Ok(_) => self.gpu.star_cubemap = Some(cubemap),
+			Err(err) => {
+				println!("Failed to render star cube map: {}", err);
+				self.use_star_cubemap = false;
+			}
+		}
+	}
 
-			self.crystal_line_targets[1 - self.target_flip].bind();
-			self.crystal_line_targets[self.target_flip].get_target(0).unwrap().bind_to_slot(0);
+	fn on_resize(&mut self) {
+		self.fit_canvas();
+		self.resize_targets();
+	}
 
-			self.quad_mesh.bind();
-			self.quad_mesh.draw(gl::TRIANGLES);
-			Framebuffer::unbind();
+	fn resize_targets(&mut self) {
+		let render_size = self.render_size();
 
-			self.target_flip = 1 - self.target_flip;
+		if let Some(ref mut graph) = self.gpu.render_graph {
+			graph.resize(render_size);
+		}
+	}
 
-			self.crystal_line_targets[self.target_flip].get_target(0).unwrap().bind_to_slot(0);
-			self.shader_fb.use_program();
-			self.quad_mesh.bind();
-			self.quad_mesh.draw(gl::TRIANGLES);
+	fn render_size(&self) -> Vec2i {
+		let scale = self.quality.current().resolution_scale;
+		let size = self.viewport.size.to_vec2() * scale;
 
-			gl::Enable(gl::DEPTH_TEST);
+		Vec2i::new((size.x as i32).max(1), (size.y as i32).max(1))
+	}
+
+	fn apply_quality(&mut self) {
+		let quality = self.quality.current();
+
+		upload_motion_blur_steps(&mut self.gpu.star_mesh, quality.motion_blur_steps);
+		self.resize_targets();
+
+		println!("Switched to {} quality", quality.name);
+	}
+
+	fn save_screenshot(&self) {
+		// The canvas can collapse to nothing, and PNG has no way to store an empty image
+		if self.viewport.size.x <= 0 || self.viewport.size.y <= 0 {
+			println!("Can't take a screenshot of an empty viewport");
+			return
+		}
+
+		let mut image = Framebuffer::read_screen_pixels(self.viewport.size);
+		image.flip_vertical();
+
+		let name = format!("{}_{:.0}.png", env!("CARGO_PKG_NAME"), self.time * 1000.0);
+		let data = png::encode(&image);
+
+		if cfg!(target_os = "emscripten") {
+			ems::download_file(&name, &data, "image/png");
+		} else {
+			use std::io::Write;
+
+			let result = std::fs::File::create(&name)
+				.and_then(|mut file| file.write_all(&data));
+
+			if let Err(err) = result {
+				println!("Failed to write screenshot {}: {}", name, err);
+				return
+			}
+		}
+
+		println!("Saved screenshot {}", name);
+	}
+
+	fn on_key_down(&mut self, key_code: u32) {
+		match key_code {
+			// B
+			66 => {
+				self.use_star_streaks = !self.use_star_streaks;
+				println!("Star streaks {}", if self.use_star_streaks { "enabled" } else { "disabled" });
+			}
+
+			// C
+			67 => {
+				self.use_star_cubemap = !self.use_star_cubemap;
+				println!("Star cube map {}", if self.use_star_cubemap { "enabled" } else { "disabled" });
+			}
+
+			// 1-4 pick a quality level, which stops it from adapting
+			49...52 => {
+				let level = (key_code - 49) as usize;
+				self.quality.adaptive = false;
+
+				if level != self.quality.level() {
+					self.quality.set_level(level);
+					self.apply_quality();
+				}
+			}
+
+			// Q
+			81 => {
+				self.quality.adaptive = !self.quality.adaptive;
+
+				let frame_time = match self.quality.avg_frame_time() {
+					Some(t) => format!("{:.1}ms", t * 1000.0),
+					None => "unmeasured".to_string(),
+				};
+
+				println!("Adaptive quality {}, at {} quality, average frame time {}",
+					if self.quality.adaptive { "enabled" } else { "disabled" }, self.quality.current().name, frame_time);
+			}
+
+			// S
+			83 => self.screenshot_requested = true,
+
+			_ => {}
 		}
 	}
 
@@ -406,8 +921,9 @@ impl MainContext {
 
 		self.touch_id = Some(id);
 		self.touch_start = pos;
-		self.touch_prev = pos;
-		self.touch_delta = Vec2::zero();
+
+		let drag_pos = self.to_drag_units(pos);
+		self.trackball.begin_drag(drag_pos);
 
 		self.is_potential_tap = true;
 	}
@@ -415,6 +931,7 @@ impl MainContext {
 	fn on_touch_up(&mut self, id: u32) {
 		if self.touch_id != Some(id) { return }
 		self.touch_id = None;
+		self.trackball.end_drag();
 
 		if self.is_touch_input && self.is_potential_tap {
 			js! {{ b"Module.requestFullscreen(1, 1)\0" }};
@@ -424,43 +941,174 @@ impl MainContext {
 	fn on_touch_move(&mut self, id: u32, pos: Vec2i) {
 		if self.touch_id != Some(id) { return }
 
-		if (pos - self.touch_start).length() > 5.0 {
+		if (pos - self.touch_start).length() > 5.0 * self.viewport.pixel_ratio {
 			self.is_potential_tap = false;
 		}
 
-		let minor = self.viewport.size.x.min(self.viewport.size.y);
+		let drag_pos = self.to_drag_units(pos);
+		self.trackball.drag(drag_pos);
+	}
+
+	// Pixels to units of the shorter side of the viewport, which the trackball works in
+	fn to_drag_units(&self, pos: Vec2i) -> Vec2 {
+		let minor = self.viewport.size.x.min(self.viewport.size.y).max(1);
+		pos.to_vec2() / Vec2::splat(minor as f32)
+	}
+
+	// Recompiles any shaders whose sources have changed on disk. Shaders that fail to compile
+	//	are left as they were
+	fn reload_shaders(&mut self) {
+		let reloader = match self.shader_reloader {
+			Some(ref mut reloader) => reloader,
+			None => return
+		};
+
+		let changed = reloader.take_changed();
+		if changed.is_empty() { return }
+
+		let crystal_preamble = self.gpu.crystal_shader_preamble.clone();
 
-		let diff = pos - self.touch_prev;
-		self.touch_delta = 0.9f32.ease_linear(self.touch_delta, diff.to_vec2() / Vec2::splat(minor as f32));
-		self.touch_prev = pos;
+		let reload_with_preamble = |shader: &mut Shader, vert: &'static str, frag: &'static str, frag_preamble: &str| {
+			if !changed.contains(&vert) && !changed.contains(&frag) { return }
+
+			let frag_src = format!("{}{}", frag_preamble, reloader.get_source(frag));
+
+			match Shader::try_new(reloader.get_source(vert), &frag_src) {
+				Ok(new_shader) => {
+					println!("Reloaded shader {} + {}", vert, frag);
+					*shader = new_shader;
+				}
+
+				Err(log) => println!("Failed to reload shader {} + {}, keeping old program:\n{}", vert, frag, log),
+			}
+		};
+
+		let reload = |shader: &mut Shader, vert: &'static str, frag: &'static str| {
+			reload_with_preamble(shader, vert, frag, "")
+		};
+
+		reload(&mut self.gpu.shader_fb, FB_SHADER_VERT_PATH, FB_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.shader_star, STAR_SHADER_VERT_PATH, STAR_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.shader_star_streak, STAR_STREAK_SHADER_VERT_PATH, STAR_STREAK_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.shader_color, BASIC_TRANSFORM_SHADER_VERT_PATH, COLOR_SHADER_FRAG_PATH);
+		reload_with_preamble(&mut self.gpu.shader_crystal, CRYSTAL_SHADER_VERT_PATH, CRYSTAL_SHADER_FRAG_PATH, &crystal_preamble);
+		reload(&mut self.gpu.shader_line_fuzz, FB_SHADER_VERT_PATH, LINE_FUZZ_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.shader_star_compose, FB_SHADER_VERT_PATH, STAR_COMPOSE_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.post_shaders.downsample, FB_SHADER_VERT_PATH, POST_DOWNSAMPLE_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.post_shaders.upsample, FB_SHADER_VERT_PATH, POST_UPSAMPLE_SHADER_FRAG_PATH);
+		reload(&mut self.gpu.post_shaders.final_pass, FB_SHADER_VERT_PATH, POST_FINAL_SHADER_FRAG_PATH);
 	}
 
+	// Sizes the canvas backing store to the window in device pixels, so that high density
+	//	screens aren't upscaled. The backing store is only touched when its size changes,
+	//	since assigning to it clears the canvas
 	fn fit_canvas(&mut self) {
 		js! { b"Module.canvas = document.getElementById('canvas')\0" };
 
-		let w = js! { b"return (Module.canvas.width = Module.canvas.style.width = window.innerWidth)\0" };
-		let h = js! { b"return (Module.canvas.height = Module.canvas.style.height = window.innerHeight)\0" };
+		let css_w = js! { b"return window.innerWidth\0" };
+		let css_h = js! { b"return window.innerHeight\0" };
+		let dpr = js! { b"return Math.round((window.devicePixelRatio || 1) * 1000)\0" } as f32 / 1000.0;
 
-		self.viewport.size = Vec2i::new(w, h);
+		let pixel_ratio = if dpr > 0.0 { dpr.min(MAX_PIXEL_RATIO) } else { 1.0 };
+
+		let prev_size = self.viewport.size;
+		let prev_css_size = self.viewport.css_size;
+		self.viewport.set_css_size(Vec2i::new(css_w, css_h), pixel_ratio);
+
+		if self.viewport.size != prev_size || self.viewport.css_size != prev_css_size {
+			let Vec2i{x: w, y: h} = self.viewport.size;
+
+			js! { (w, h, css_w, css_h) b"
+				Module.canvas.width = $0;
+				Module.canvas.height = $1;
+				Module.canvas.style.width = $2 + 'px';
+				Module.canvas.style.height = $3 + 'px';
+			\0" };
+		}
 	}
 
 	fn build_crystal(&mut self) {
-		use crystal::Crystal;
-
 		println!("Generating crystal");
 
 		let mut crystal = Crystal::new();
 		crystal.radius = 0.5;
-
 		crystal.generate();
+
+		self.crystal = Some(crystal);
+		self.upload_crystal();
+
+		self.crystal_refract_idx = thread_rng().gen_range(1.0 / 4.0, 1.0 / 1.01);
+	}
+
+	fn upload_crystal(&mut self) {
+		let crystal = match self.crystal {
+			Some(ref crystal) => crystal,
+			None => return
+		};
+
 		self.cmbuilder.clear();
-		crystal.build_faces(&mut self.cmbuilder);
-		self.cmbuilder.upload_to(&mut self.crystal_mesh);
+
+		if self.gpu.crystal_derived_normals {
+			crystal.build_shared_faces(&mut self.cmbuilder);
+		} else {
+			crystal.build_faces(&mut self.cmbuilder);
+		}
+
+		self.cmbuilder.upload_to(&mut self.gpu.crystal_mesh);
 
 		self.cmbuilder.clear();
 		crystal.build_edges(&mut self.cmbuilder, 0.05);
-		self.cmbuilder.upload_to(&mut self.crystal_mesh_lines);
+		self.cmbuilder.upload_to(&mut self.gpu.crystal_mesh_lines);
+	}
 
-		self.crystal_refract_idx = thread_rng().gen_range(1.0 / 4.0, 1.0 / 1.01);
+	fn on_hidden(&mut self) {
+		println!("Page hidden, pausing");
+
+		// Let go of any drag in progress, since its end event may never arrive
+		self.touch_id = None;
+		self.trackball.cancel_drag();
+	}
+
+	// The main loop was paused while hidden, so the next frame shouldn't count that time
+	fn on_shown(&mut self) {
+		println!("Page visible, resuming");
+		self.clock.reset();
+	}
+
+	fn on_context_lost(&mut self) {
+		println!("WebGL context lost");
+		self.context_lost = true;
+	}
+
+	// Every GL resource is rebuilt, while everything else carries on as it was. The old
+	//	resources are discarded without deleting them, since their handles belonged to the
+	//	lost context
+	fn on_context_restored(&mut self) {
+		use std::mem;
+
+		println!("WebGL context restored, rebuilding GL resources");
+
+		rendering::capabilities::init(unsafe { ems::emscripten_webgl_get_current_context() });
+
+		let live_before = resource_tracking::total_live_count();
+		let stale = mem::replace(&mut self.gpu, GpuResources::new(&self.stars));
+		let live_fresh = resource_tracking::total_live_count() - live_before;
+
+		resource_tracking::discard(stale);
+
+		// Every GL object is owned by GpuResources, so discarding the old ones should leave
+		//	exactly what was just created
+		debug_assert!(resource_tracking::total_live_count() == live_fresh,
+			"{} GL objects live after discarding the lost context's, expected {}", resource_tracking::total_live_count(), live_fresh);
+		self.context_lost = false;
+
+		self.upload_crystal();
+		upload_motion_blur_steps(&mut self.gpu.star_mesh, self.quality.current().motion_blur_steps);
+		self.resize_targets();
+
+		// Shaders were rebuilt from the sources compiled into the binary
+		if let Some(ref mut reloader) = self.shader_reloader {
+			reloader.mark_all_changed();
+		}
 	}
 }
\ No newline at end of file
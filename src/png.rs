@@ -0,0 +1,882 @@
+use common::*;
+use rendering::image::{Image, Rgba8};
+
+const SIGNATURE: &'static [u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Largest block deflate can store without compression
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// PNG allows up to 2^31-1, but nothing larger than this could be uploaded as a texture anyway.
+//	It also bounds how much memory a decode can ask for, however small the file is
+const MAX_DIMENSION: usize = 16384;
+
+const MAX_PALETTE_ENTRIES: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [3,4,5,6,7,8,9,10,11,13,15,17,19,23,27,31,35,43,51,59,67,83,99,115,131,163,195,227,258];
+const LENGTH_EXTRA: [u8; 29] = [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0];
+const DIST_BASE: [u16; 30] = [1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577];
+const DIST_EXTRA: [u8; 30] = [0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13];
+
+// Order code length code lengths are stored in, in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16,17,18,0,8,7,9,6,10,5,11,4,12,3,13,2,14,1,15];
+
+// Encodes an 8 bit RGBA image with rows ordered top down. Image data is stored
+//	uncompressed, which is plenty fast for screenshots but makes for large files
+pub fn encode(image: &Image<Rgba8>) -> Vec<u8> {
+	assert!(image.size.x > 0 && image.size.y > 0, "PNG images can't be empty");
+
+	let mut png = Vec::new();
+	png.extend_from_slice(SIGNATURE);
+
+	let mut header = Vec::with_capacity(13);
+	push_u32(&mut header, image.size.x as u32);
+	push_u32(&mut header, image.size.y as u32);
+	header.extend_from_slice(&[
+		8, // Bit depth
+		6, // Color type: RGBA
+		0, // Compression method: deflate
+		0, // Filter method: adaptive
+		0, // Interlace method: none
+	]);
+
+	write_chunk(&mut png, b"IHDR", &header);
+
+	// Each scanline is prefixed with its filter type, 0 for none
+	let mut scanlines = Vec::with_capacity(image.pixels.len() * 4 + image.size.y as usize);
+	for row in image.rows() {
+		scanlines.push(0);
+
+		for px in row {
+			scanlines.extend_from_slice(px);
+		}
+	}
+
+	write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+	write_chunk(&mut png, b"IEND", &[]);
+
+	png
+}
+
+// Decodes any non-interlaced PNG into 8 bit RGBA with rows ordered top down.
+//	16 bit channels are truncated
+pub fn decode(data: &[u8]) -> Result<Image<Rgba8>, String> {
+	if !data.starts_with(SIGNATURE) {
+		return Err("Missing PNG signature".to_string())
+	}
+
+	let mut header = None;
+	let mut palette: Vec<Rgba8> = Vec::new();
+	let mut transparency: Option<&[u8]> = None;
+	let mut compressed = Vec::new();
+
+	let mut pos = SIGNATURE.len();
+
+	loop {
+		if pos + 8 > data.len() {
+			return Err("Unexpected end of file".to_string())
+		}
+
+		let len = read_u32(&data[pos..]) as usize;
+		let ty = &data[pos+4 .. pos+8];
+
+		// Length comes straight from the file, so it can be anything up to 4G
+		let end = match (pos + 12).checked_add(len) {
+			Some(end) if end <= data.len() => end,
+			_ => return Err(format!("Chunk {} runs past end of file", String::from_utf8_lossy(ty)))
+		};
+
+		let chunk = &data[pos+8 .. pos+8+len];
+		if crc32(&data[pos+4 .. pos+8+len]) != read_u32(&data[pos+8+len..]) {
+			return Err(format!("Chunk {} is corrupt", String::from_utf8_lossy(ty)))
+		}
+
+		pos = end;
+
+		match ty {
+			b"IHDR" => header = Some(Header::parse(chunk)?),
+			b"PLTE" => {
+				if chunk.len() % 3 != 0 || chunk.len() / 3 > MAX_PALETTE_ENTRIES {
+					return Err("Malformed PLTE chunk".to_string())
+				}
+
+				palette = chunk.chunks(3).map(|c| [c[0], c[1], c[2], 255]).collect();
+			}
+
+			b"tRNS" => transparency = Some(chunk),
+			b"IDAT" => compressed.extend_from_slice(chunk),
+			b"IEND" => break,
+
+			// Ancillary chunks can be ignored
+			_ if ty[0] & 0x20 != 0 => {}
+			_ => return Err(format!("Unsupported critical chunk {}", String::from_utf8_lossy(ty))),
+		}
+	}
+
+	let header = match header {
+		Some(h) => h,
+		None => return Err("Missing IHDR chunk".to_string())
+	};
+
+	if let Some(trns) = transparency {
+		for (entry, &alpha) in palette.iter_mut().zip(trns) {
+			entry[3] = alpha;
+		}
+	}
+
+	let raw = inflate_zlib(&compressed, header.data_len())?;
+	let scanlines = header.unfilter(&raw)?;
+	let pixels = header.expand(&scanlines, &palette, transparency)?;
+
+	Ok(Image::new(Vec2i::new(header.width as i32, header.height as i32), pixels))
+}
+
+struct Header {
+	width: usize,
+	height: usize,
+	bit_depth: usize,
+	color_type: u8,
+
+	// Bytes per scanline, excluding the filter type byte
+	row_bytes: usize,
+}
+
+impl Header {
+	fn parse(chunk: &[u8]) -> Result<Header, String> {
+		if chunk.len() != 13 {
+			return Err("Malformed IHDR chunk".to_string())
+		}
+
+		let header = Header {
+			width: read_u32(&chunk[0..]) as usize,
+			height: read_u32(&chunk[4..]) as usize,
+			bit_depth: chunk[8] as usize,
+			color_type: chunk[9],
+			row_bytes: 0,
+		};
+
+		if header.width == 0 || header.height == 0 || header.width > MAX_DIMENSION || header.height > MAX_DIMENSION {
+			return Err(format!("Invalid image size {}x{}", header.width, header.height))
+		}
+
+		if chunk[12] != 0 {
+			return Err("Interlaced PNGs aren't supported".to_string())
+		}
+
+		let valid_depth = match header.color_type {
+			0 => [1, 2, 4, 8, 16].contains(&header.bit_depth),
+			3 => [1, 2, 4, 8].contains(&header.bit_depth),
+			2 | 4 | 6 => [8, 16].contains(&header.bit_depth),
+			_ => return Err(format!("Invalid color type {}", header.color_type)),
+		};
+
+		if !valid_depth {
+			return Err(format!("Invalid bit depth {} for color type {}", header.bit_depth, header.color_type))
+		}
+
+		// Everything sized from the header is checked here once, so later arithmetic can't overflow
+		let row_bits = header.width.checked_mul(header.channels() * header.bit_depth);
+		let row_bytes = match row_bits.and_then(|bits| bits.checked_add(7)) {
+			Some(bits) => bits / 8,
+			None => return Err("Image is too large".to_string())
+		};
+
+		// Can't fail with MAX_DIMENSION as it is, but keeps the arithmetic below honest if it grows
+		let fits = (row_bytes + 1).checked_mul(header.height).is_some()
+			&& header.width.checked_mul(header.height).map_or(false, |count| count <= i32::max_value() as usize);
+
+		if !fits {
+			return Err("Image is too large".to_string())
+		}
+
+		Ok(Header { row_bytes, ..header })
+	}
+
+	// Size of the decompressed image data, scanlines with their filter type bytes
+	fn data_len(&self) -> usize {
+		(self.row_bytes + 1) * self.height
+	}
+
+	fn channels(&self) -> usize {
+		match self.color_type {
+			0 | 3 => 1,
+			4 => 2,
+			2 => 3,
+			_ => 4,
+		}
+	}
+
+	// Distance in bytes to the corresponding byte of the previous pixel, at least 1
+	fn filter_stride(&self) -> usize {
+		((self.channels() * self.bit_depth + 7) / 8).max(1)
+	}
+
+	// Undoes per-scanline filtering, returning scanlines without filter type bytes
+	fn unfilter(&self, raw: &[u8]) -> Result<Vec<u8>, String> {
+		let row_bytes = self.row_bytes;
+		let stride = self.filter_stride();
+
+		if raw.len() < self.data_len() {
+			return Err("Image data is too short".to_string())
+		}
+
+		let mut out = vec![0u8; row_bytes * self.height];
+
+		for (y, line) in raw.chunks(row_bytes + 1).take(self.height).enumerate() {
+			let (filter, line) = (line[0], &line[1..]);
+			let (prev_rows, row) = out.split_at_mut(y * row_bytes);
+			let row = &mut row[..row_bytes];
+
+			let prev = if y > 0 { &prev_rows[(y-1) * row_bytes..] } else { &[][..] };
+			let up = |i: usize| if y > 0 { prev[i] } else { 0 };
+
+			for i in 0..row_bytes {
+				let left = if i >= stride { row[i - stride] } else { 0 };
+				let up_left = if i >= stride { up(i - stride) } else { 0 };
+
+				let predicted = match filter {
+					0 => 0,
+					1 => left,
+					2 => up(i),
+					3 => ((left as u16 + up(i) as u16) / 2) as u8,
+					4 => paeth(left, up(i), up_left),
+					_ => return Err(format!("Invalid filter type {}", filter)),
+				};
+
+				row[i] = line[i].wrapping_add(predicted);
+			}
+		}
+
+		Ok(out)
+	}
+
+	fn expand(&self, scanlines: &[u8], palette: &[Rgba8], transparency: Option<&[u8]>) -> Result<Vec<Rgba8>, String> {
+		let row_bytes = self.row_bytes;
+		let channels = self.channels();
+		let depth = self.bit_depth;
+
+		// Grayscale and truecolor images can mark a single color as transparent
+		let transparent_sample = |i: usize| match transparency {
+			Some(t) if t.len() >= (i + 1) * 2 => Some(((t[i*2] as u16) << 8) | t[i*2+1] as u16),
+			_ => None
+		};
+
+		let mut pixels = Vec::with_capacity(self.width * self.height);
+
+		for row in scanlines.chunks(row_bytes) {
+			// Samples are full precision, before any scaling
+			let sample = |idx: usize| -> u16 {
+				match depth {
+					16 => ((row[idx*2] as u16) << 8) | row[idx*2 + 1] as u16,
+					8 => row[idx] as u16,
+					_ => {
+						let bit = idx * depth;
+						let shift = 8 - depth - bit % 8;
+						((row[bit / 8] >> shift) as u16) & ((1 << depth) - 1)
+					}
+				}
+			};
+
+			// Scales a sample to 8 bits
+			let scale = |v: u16| -> u8 {
+				match depth {
+					16 => (v >> 8) as u8,
+					8 => v as u8,
+					_ => (v as u32 * 255 / ((1 << depth) - 1)) as u8,
+				}
+			};
+
+			for x in 0..self.width {
+				let base = x * channels;
+
+				let px = match self.color_type {
+					0 => {
+						let v = sample(base);
+						let alpha = if transparent_sample(0) == Some(v) { 0 } else { 255 };
+						let g = scale(v);
+						[g, g, g, alpha]
+					}
+
+					2 => {
+						let (r, g, b) = (sample(base), sample(base + 1), sample(base + 2));
+						let opaque = transparent_sample(0) != Some(r)
+							|| transparent_sample(1) != Some(g)
+							|| transparent_sample(2) != Some(b);
+
+						[scale(r), scale(g), scale(b), if opaque { 255 } else { 0 }]
+					}
+
+					3 => match palette.get(sample(base) as usize) {
+						Some(&px) => px,
+						None => return Err("Palette index out of range".to_string()),
+					},
+
+					4 => {
+						let g = scale(sample(base));
+						[g, g, g, scale(sample(base + 1))]
+					}
+
+					_ => [scale(sample(base)), scale(sample(base + 1)), scale(sample(base + 2)), scale(sample(base + 3))],
+				};
+
+				pixels.push(px);
+			}
+		}
+
+		Ok(pixels)
+	}
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+	let p = a as i16 + b as i16 - c as i16;
+	let pa = (p - a as i16).abs();
+	let pb = (p - b as i16).abs();
+	let pc = (p - c as i16).abs();
+
+	if pa <= pb && pa <= pc { a }
+	else if pb <= pc { b }
+	else { c }
+}
+
+// Fails as soon as the output grows past max_len, so corrupt data can't run away with memory
+fn inflate_zlib(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+	if data.len() < 6 {
+		return Err("Image data is too short".to_string())
+	}
+
+	let (cmf, flg) = (data[0], data[1]);
+	if cmf & 0x0F != 8 || ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+		return Err("Invalid zlib header".to_string())
+	}
+
+	if flg & 0x20 != 0 {
+		return Err("zlib preset dictionaries aren't supported".to_string())
+	}
+
+	let out = inflate(&data[2..], max_len)?;
+
+	if adler32(&out) != read_u32(&data[data.len()-4..]) {
+		return Err("Image data checksum mismatch".to_string())
+	}
+
+	Ok(out)
+}
+
+struct BitReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+	bit_buf: u32,
+	bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+	fn bits(&mut self, count: u32) -> Result<u32, String> {
+		while self.bit_count < count {
+			let byte = match self.data.get(self.pos) {
+				Some(&b) => b,
+				None => return Err("Unexpected end of compressed data".to_string())
+			};
+
+			self.bit_buf |= (byte as u32) << self.bit_count;
+			self.bit_count += 8;
+			self.pos += 1;
+		}
+
+		let v = self.bit_buf & ((1u32 << count) - 1);
+		self.bit_buf >>= count;
+		self.bit_count -= count;
+		Ok(v)
+	}
+
+	fn align_to_byte(&mut self) {
+		self.bit_buf = 0;
+		self.bit_count = 0;
+	}
+}
+
+// Canonical huffman code, decoded a bit at a time
+struct Huffman {
+	counts: [u16; 16],
+	symbols: Vec<u16>,
+}
+
+impl Huffman {
+	fn new(lengths: &[u8]) -> Huffman {
+		let mut counts = [0u16; 16];
+		for &len in lengths {
+			counts[len as usize] += 1;
+		}
+		counts[0] = 0;
+
+		let mut offsets = [0u16; 16];
+		for len in 1..15 {
+			offsets[len + 1] = offsets[len] + counts[len];
+		}
+
+		let mut symbols = vec![0u16; lengths.len()];
+		for (symbol, &len) in lengths.iter().enumerate() {
+			if len == 0 { continue }
+
+			symbols[offsets[len as usize] as usize] = symbol as u16;
+			offsets[len as usize] += 1;
+		}
+
+		Huffman { counts, symbols }
+	}
+
+	fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+		let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+		for len in 1..16 {
+			code |= reader.bits(1)? as i32;
+			let count = self.counts[len] as i32;
+
+			if code - count < first {
+				return Ok(self.symbols[(index + code - first) as usize])
+			}
+
+			index += count;
+			first = (first + count) << 1;
+			code <<= 1;
+		}
+
+		Err("Invalid huffman code".to_string())
+	}
+}
+
+fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+	let mut reader = BitReader { data, pos: 0, bit_buf: 0, bit_count: 0 };
+	let mut out = Vec::new();
+
+	loop {
+		let is_final = reader.bits(1)? == 1;
+
+		match reader.bits(2)? {
+			0 => {
+				reader.align_to_byte();
+
+				let pos = reader.pos;
+				if pos + 4 > data.len() {
+					return Err("Unexpected end of compressed data".to_string())
+				}
+
+				let len = data[pos] as usize | (data[pos+1] as usize) << 8;
+				let nlen = data[pos+2] as usize | (data[pos+3] as usize) << 8;
+				if len != !nlen & 0xFFFF {
+					return Err("Stored block length doesn't match its complement".to_string())
+				}
+
+				let block = &data[pos+4..];
+
+				if block.len() < len {
+					return Err("Unexpected end of compressed data".to_string())
+				}
+
+				if out.len() + len > max_len {
+					return Err(too_much_data())
+				}
+
+				out.extend_from_slice(&block[..len]);
+				reader.pos = pos + 4 + len;
+			}
+
+			1 => {
+				let mut lengths = [0u8; 288];
+				for (i, len) in lengths.iter_mut().enumerate() {
+					*len = match i {
+						0...143 => 8,
+						144...255 => 9,
+						256...279 => 7,
+						_ => 8,
+					};
+				}
+
+				let literals = Huffman::new(&lengths);
+				let distances = Huffman::new(&[5u8; 30]);
+				inflate_block(&mut reader, &mut out, &literals, &distances, max_len)?;
+			}
+
+			2 => {
+				let (literals, distances) = read_dynamic_tables(&mut reader)?;
+				inflate_block(&mut reader, &mut out, &literals, &distances, max_len)?;
+			}
+
+			_ => return Err("Invalid deflate block type".to_string()),
+		}
+
+		if is_final { break }
+	}
+
+	Ok(out)
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+	let literal_count = reader.bits(5)? as usize + 257;
+	let distance_count = reader.bits(5)? as usize + 1;
+	let code_length_count = reader.bits(4)? as usize + 4;
+
+	let mut code_lengths = [0u8; 19];
+	for &idx in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+		code_lengths[idx] = reader.bits(3)? as u8;
+	}
+
+	let code_length_codes = Huffman::new(&code_lengths);
+	let mut lengths = Vec::with_capacity(literal_count + distance_count);
+
+	while lengths.len() < literal_count + distance_count {
+		let (value, repeat) = match code_length_codes.decode(reader)? {
+			symbol @ 0...15 => (symbol as u8, 1),
+
+			16 => match lengths.last() {
+				Some(&prev) => (prev, 3 + reader.bits(2)?),
+				None => return Err("Repeated code length with no previous length".to_string()),
+			},
+
+			17 => (0, 3 + reader.bits(3)?),
+			_ => (0, 11 + reader.bits(7)?),
+		};
+
+		for _ in 0..repeat {
+			lengths.push(value);
+		}
+	}
+
+	if lengths.len() > literal_count + distance_count {
+		return Err("Too many code lengths".to_string())
+	}
+
+	let (literals, distances) = lengths.split_at(literal_count);
+	Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, max_len: usize) -> Result<(), String> {
+	loop {
+		let symbol = literals.decode(reader)? as usize;
+
+		if symbol < 256 {
+			if out.len() >= max_len {
+				return Err(too_much_data())
+			}
+
+			out.push(symbol as u8);
+			continue
+		}
+
+		if symbol == 256 { return Ok(()) }
+
+		let symbol = symbol - 257;
+		if symbol >= LENGTH_BASE.len() {
+			return Err("Invalid length symbol".to_string())
+		}
+
+		let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+		let symbol = distances.decode(reader)? as usize;
+		if symbol >= DIST_BASE.len() {
+			return Err("Invalid distance symbol".to_string())
+		}
+
+		let distance = DIST_BASE[symbol] as usize + reader.bits(DIST_EXTRA[symbol] as u32)? as usize;
+
+		if distance > out.len() {
+			return Err("Distance runs past start of output".to_string())
+		}
+
+		if out.len() + length > max_len {
+			return Err(too_much_data())
+		}
+
+		// Copies can overlap their own output, so go a byte at a time
+		let start = out.len() - distance;
+		for i in 0..length {
+			let byte = out[start + i];
+			out.push(byte);
+		}
+	}
+}
+
+fn too_much_data() -> String {
+	"Image data is larger than the image".to_string()
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+	(buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | buf[3] as u32
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+	buf.extend_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+}
+
+fn write_chunk(png: &mut Vec<u8>, ty: &[u8; 4], data: &[u8]) {
+	push_u32(png, data.len() as u32);
+
+	let crc_start = png.len();
+	png.extend_from_slice(ty);
+	png.extend_from_slice(data);
+
+	let crc = crc32(&png[crc_start..]);
+	push_u32(png, crc);
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+	let block_count = data.len() / MAX_STORED_BLOCK + 1;
+	let mut out = Vec::with_capacity(data.len() + block_count * 5 + 6);
+
+	// CMF/FLG: deflate with a 32K window, no preset dictionary, fastest compression
+	out.extend_from_slice(&[0x78, 0x01]);
+
+	let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+
+	// Empty input still needs a final block
+	if blocks.peek().is_none() {
+		out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+	}
+
+	while let Some(block) = blocks.next() {
+		let is_final = blocks.peek().is_none();
+		let len = block.len() as u16;
+
+		out.push(is_final as u8);
+		out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+		out.extend_from_slice(block);
+	}
+
+	push_u32(&mut out, adler32(data));
+
+	out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut table = [0u32; 256];
+
+	for (i, entry) in table.iter_mut().enumerate() {
+		let mut c = i as u32;
+
+		for _ in 0..8 {
+			c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+		}
+
+		*entry = c;
+	}
+
+	!data.iter().fold(!0u32, |crc, &b| table[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+	const MOD_ADLER: u32 = 65521;
+
+	let (mut a, mut b) = (1u32, 0u32);
+
+	// Sums can be deferred for 5552 bytes before they could overflow
+	for chunk in data.chunks(5552) {
+		for &byte in chunk {
+			a += byte as u32;
+			b += a;
+		}
+
+		a %= MOD_ADLER;
+		b %= MOD_ADLER;
+	}
+
+	(b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_image(width: i32, height: i32) -> Image<Rgba8> {
+		let pixels = (0..width*height)
+			.map(|i| [i as u8, (i >> 8) as u8, (i * 7) as u8, 255 - i as u8])
+			.collect();
+
+		Image::new(Vec2i::new(width, height), pixels)
+	}
+
+	fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+		let mut header = Vec::new();
+		push_u32(&mut header, width);
+		push_u32(&mut header, height);
+		header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+		header
+	}
+
+	fn build_png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+		let mut png = SIGNATURE.to_vec();
+		for &(ty, data) in chunks {
+			write_chunk(&mut png, ty, data);
+		}
+		png
+	}
+
+	#[test]
+	fn round_trip() {
+		for &(w, h) in &[(1, 1), (3, 5), (64, 48)] {
+			let image = test_image(w, h);
+			let decoded = decode(&encode(&image)).unwrap();
+
+			assert_eq!(decoded.size, image.size);
+			assert_eq!(decoded.pixels, image.pixels);
+		}
+	}
+
+	#[test]
+	fn round_trip_multiple_stored_blocks() {
+		// Over 64K of scanlines, so the encoder has to split it across stored blocks
+		let image = test_image(200, 100);
+		let decoded = decode(&encode(&image)).unwrap();
+
+		assert_eq!(decoded.pixels, image.pixels);
+	}
+
+	#[test]
+	#[should_panic]
+	fn encode_rejects_empty_image() {
+		encode(&test_image(0, 4));
+	}
+
+	#[test]
+	fn palette() {
+		let plte = [255, 0, 0, 0, 255, 0];
+		let trns = [128];
+		let idat = zlib_stored(&[0, 0b0100_0000]);
+
+		let png = build_png(&[
+			(b"IHDR", &ihdr(2, 1, 1, 3)),
+			(b"PLTE", &plte),
+			(b"tRNS", &trns),
+			(b"IDAT", &idat),
+			(b"IEND", &[]),
+		]);
+
+		let decoded = decode(&png).unwrap();
+		assert_eq!(decoded.pixels, vec![[255, 0, 0, 128], [0, 255, 0, 255]]);
+	}
+
+	#[test]
+	fn rejects_missing_signature() {
+		assert!(decode(b"not a png").is_err());
+		assert!(decode(&[]).is_err());
+	}
+
+	#[test]
+	fn rejects_truncated_file() {
+		let png = encode(&test_image(4, 4));
+
+		for len in 0..png.len() {
+			assert!(decode(&png[..len]).is_err());
+		}
+	}
+
+	#[test]
+	fn rejects_corrupt_chunk() {
+		let mut png = encode(&test_image(4, 4));
+		let last = png.len() - 20;
+		png[last] ^= 0xFF;
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_oversized_chunk_length() {
+		let mut png = SIGNATURE.to_vec();
+		push_u32(&mut png, 0xFFFF_FFFF);
+		png.extend_from_slice(b"IHDR");
+		png.extend_from_slice(&[0; 8]);
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_invalid_dimensions() {
+		let idat = zlib_stored(&[0, 0, 0, 0, 0]);
+
+		for &(w, h) in &[(0, 1), (1, 0), (0x8000_0000, 1), (1, 0x8000_0000), (0xFFFF_FFFF, 0xFFFF_FFFF), (16385, 1), (1, 16385)] {
+			let png = build_png(&[
+				(b"IHDR", &ihdr(w, h, 8, 6)),
+				(b"IDAT", &idat),
+				(b"IEND", &[]),
+			]);
+
+			assert!(decode(&png).is_err(), "{}x{} should be rejected", w, h);
+		}
+	}
+
+	#[test]
+	fn rejects_malformed_palette() {
+		let idat = zlib_stored(&[0, 0]);
+		let oversized = [0u8; 257 * 3];
+
+		for &plte in &[&[0u8; 4][..], &[0u8; 2][..], &oversized[..]] {
+			let png = build_png(&[
+				(b"IHDR", &ihdr(1, 1, 8, 3)),
+				(b"PLTE", plte),
+				(b"IDAT", &idat),
+				(b"IEND", &[]),
+			]);
+
+			assert!(decode(&png).is_err());
+		}
+	}
+
+	#[test]
+	fn rejects_out_of_range_palette_index() {
+		let png = build_png(&[
+			(b"IHDR", &ihdr(1, 1, 8, 3)),
+			(b"PLTE", &[0, 0, 0]),
+			(b"IDAT", &zlib_stored(&[0, 1])),
+			(b"IEND", &[]),
+		]);
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_stored_block_length_mismatch() {
+		let mut idat = zlib_stored(&[0, 1, 2, 3, 4]);
+
+		// NLEN directly follows the block header byte and LEN
+		idat[5] ^= 0x01;
+
+		let png = build_png(&[
+			(b"IHDR", &ihdr(1, 1, 8, 6)),
+			(b"IDAT", &idat),
+			(b"IEND", &[]),
+		]);
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_short_image_data() {
+		let png = build_png(&[
+			(b"IHDR", &ihdr(2, 2, 8, 6)),
+			(b"IDAT", &zlib_stored(&[0, 1, 2, 3, 4])),
+			(b"IEND", &[]),
+		]);
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_excess_image_data() {
+		// A 1x1 RGBA image only has 5 bytes of data, filter type included
+		let png = build_png(&[
+			(b"IHDR", &ihdr(1, 1, 8, 6)),
+			(b"IDAT", &zlib_stored(&[0; 100])),
+			(b"IEND", &[]),
+		]);
+
+		assert!(decode(&png).is_err());
+	}
+
+	#[test]
+	fn rejects_invalid_filter_type() {
+		let png = build_png(&[
+			(b"IHDR", &ihdr(1, 1, 8, 6)),
+			(b"IDAT", &zlib_stored(&[5, 1, 2, 3, 4])),
+			(b"IEND", &[]),
+		]);
+
+		assert!(decode(&png).is_err());
+	}
+}
@@ -0,0 +1,341 @@
+use common::*;
+
+use rendering::gl;
+use rendering::shader::Shader;
+use rendering::texture::Texture;
+use rendering::mesh_builder::Mesh;
+use rendering::format::ColorFormat;
+use rendering::framebuffer::{Framebuffer, FramebufferBuilder};
+use rendering::render_graph::Target;
+use resources::*;
+
+use std::any::Any;
+
+// The bloom chain is allocated up front, settings can only use fewer levels
+pub const MAX_BLOOM_LEVELS: usize = 6;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BloomSettings {
+	pub enabled: bool,
+
+	// Brightness above which colors start to bloom, and how softly they fade in around it
+	pub threshold: f32,
+	pub soft_knee: f32,
+
+	pub intensity: f32,
+
+	// Scales the upsample filter footprint, in texels of the smaller level
+	pub radius: f32,
+
+	pub levels: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapSettings {
+	pub enabled: bool,
+	pub exposure: f32,
+
+	// Scene brightness that maps to white
+	pub white_point: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VignetteSettings {
+	pub enabled: bool,
+	pub strength: f32,
+
+	// Distance from the center at which darkening is complete, in units of viewport height
+	pub radius: f32,
+	pub softness: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct GrainSettings {
+	pub enabled: bool,
+	pub intensity: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PostSettings {
+	pub bloom: BloomSettings,
+	pub tone_map: ToneMapSettings,
+	pub vignette: VignetteSettings,
+	pub grain: GrainSettings,
+}
+
+impl Default for PostSettings {
+	fn default() -> Self {
+		PostSettings {
+			bloom: BloomSettings {
+				enabled: true,
+				threshold: 0.6,
+				soft_knee: 0.5,
+				intensity: 0.8,
+				radius: 1.0,
+				levels: 5,
+			},
+
+			tone_map: ToneMapSettings {
+				enabled: true,
+				exposure: 2.0,
+				white_point: 11.2,
+			},
+
+			vignette: VignetteSettings {
+				enabled: true,
+				strength: 0.6,
+				radius: 1.1,
+				softness: 0.8,
+			},
+
+			grain: GrainSettings {
+				enabled: true,
+				intensity: 0.03,
+			},
+		}
+	}
+}
+
+uniform_block! {
+	pub struct DownsampleUniforms {
+		color: i32 = "u_color",
+		texel_size: Vec2 = "u_texel_size",
+
+		apply_threshold: i32 = "u_apply_threshold",
+		threshold: f32 = "u_threshold",
+		soft_knee: f32 = "u_soft_knee",
+	}
+}
+
+uniform_block! {
+	pub struct UpsampleUniforms {
+		color: i32 = "u_color",
+		texel_size: Vec2 = "u_texel_size",
+		radius: f32 = "u_radius",
+	}
+}
+
+uniform_block! {
+	pub struct FinalUniforms {
+		scene: i32 = "u_scene",
+		bloom: i32 = "u_bloom",
+
+		bloom_enabled: i32 = "u_bloom_enabled",
+		bloom_intensity: f32 = "u_bloom_intensity",
+
+		tone_map_enabled: i32 = "u_tone_map_enabled",
+		exposure: f32 = "u_exposure",
+		white_point: f32 = "u_white_point",
+
+		vignette_enabled: i32 = "u_vignette_enabled",
+		vignette_strength: f32 = "u_vignette_strength",
+		vignette_radius: f32 = "u_vignette_radius",
+		vignette_softness: f32 = "u_vignette_softness",
+
+		grain_enabled: i32 = "u_grain_enabled",
+		grain_intensity: f32 = "u_grain_intensity",
+
+		aspect: f32 = "u_aspect",
+		time: f32 = "u_time",
+	}
+}
+
+pub struct PostShaders {
+	pub downsample: Shader,
+	pub upsample: Shader,
+	pub final_pass: Shader,
+}
+
+impl PostShaders {
+	pub fn new() -> Self {
+		PostShaders {
+			downsample: Shader::new(FB_SHADER_VERT_SRC, POST_DOWNSAMPLE_SHADER_FRAG_SRC),
+			upsample: Shader::new(FB_SHADER_VERT_SRC, POST_UPSAMPLE_SHADER_FRAG_SRC),
+			final_pass: Shader::new(FB_SHADER_VERT_SRC, POST_FINAL_SHADER_FRAG_SRC),
+		}
+	}
+}
+
+// The scene is rendered into an HDR target where available, which is then bloomed, tone mapped
+//	and presented to the screen by render
+pub struct PostStack {
+	scene: Framebuffer,
+	bloom_chain: Vec<Framebuffer>,
+	size: Vec2i,
+}
+
+impl PostStack {
+	pub fn new() -> Self {
+		// Bloom relies on bilinear taps, so HDR is only worth having if it can be filtered
+		let hdr_format = ColorFormat::Rgba16F.resolve();
+		let hdr_format = if hdr_format.supports_linear_filtering() {
+			hdr_format
+		} else {
+			println!("{:?} can't be linearly filtered, post processing falls back to Rgba8", hdr_format);
+			ColorFormat::Rgba8
+		};
+
+		let scene = FramebufferBuilder::new_unsized()
+			.add_target_with_format(hdr_format)
+			.finalize()
+			.or_else(|_| FramebufferBuilder::new_unsized().add_target().finalize())
+			.expect("Failed to create post processing scene target");
+
+		let format = scene.get_target_format(0).unwrap();
+
+		let bloom_chain = (0..MAX_BLOOM_LEVELS)
+			.map(|_| FramebufferBuilder::new_unsized()
+				.add_target_with_format(format)
+				.finalize()
+				.expect("Failed to create bloom target"))
+			.collect();
+
+		PostStack {
+			scene,
+			bloom_chain,
+			size: Vec2i::zero(),
+		}
+	}
+
+	// The target everything before post processing renders into
+	pub fn scene(&mut self) -> &mut Framebuffer {
+		&mut self.scene
+	}
+
+	// Presents the scene to the screen, which may be larger than the targets if the scene
+	//	is rendered at a reduced resolution
+	pub fn render(&mut self, shaders: &PostShaders, settings: &PostSettings, quad_mesh: &Mesh, output_size: Vec2i, time: f32) {
+		let bloom_levels = if settings.bloom.enabled {
+			settings.bloom.levels.max(1).min(self.bloom_chain.len())
+		} else {
+			0
+		};
+
+		unsafe {
+			gl::Disable(gl::DEPTH_TEST);
+			gl::Disable(gl::BLEND);
+		}
+
+		quad_mesh.bind();
+
+		shaders.downsample.use_program();
+
+		for level in 0..bloom_levels {
+			let src_size = if level == 0 {
+				self.scene.get_target(0).unwrap().bind_to_slot(0);
+				self.size
+			} else {
+				self.bloom_chain[level-1].get_target(0).unwrap().bind_to_slot(0);
+				bloom_level_size(self.size, level-1)
+			};
+
+			let Vec2i{x: w, y: h} = bloom_level_size(self.size, level);
+			self.bloom_chain[level].bind();
+			unsafe { gl::Viewport(0, 0, w, h); }
+
+			shaders.downsample.set_uniforms(&DownsampleUniforms {
+				color: 0,
+				texel_size: texel_size(src_size),
+
+				apply_threshold: (level == 0) as i32,
+				threshold: settings.bloom.threshold,
+				soft_knee: settings.bloom.soft_knee,
+			});
+
+			quad_mesh.draw(gl::TRIANGLES);
+		}
+
+		// Each level is blurred onto the one above it, accumulating into the first
+		if bloom_levels > 1 {
+			unsafe {
+				gl::Enable(gl::BLEND);
+				gl::BlendFunc(gl::ONE, gl::ONE);
+			}
+
+			shaders.upsample.use_program();
+
+			for level in (1..bloom_levels).rev() {
+				self.bloom_chain[level].get_target(0).unwrap().bind_to_slot(0);
+
+				let Vec2i{x: w, y: h} = bloom_level_size(self.size, level-1);
+				self.bloom_chain[level-1].bind();
+				unsafe { gl::Viewport(0, 0, w, h); }
+
+				shaders.upsample.set_uniforms(&UpsampleUniforms {
+					color: 0,
+					texel_size: texel_size(bloom_level_size(self.size, level)),
+					radius: settings.bloom.radius,
+				});
+
+				quad_mesh.draw(gl::TRIANGLES);
+			}
+
+			unsafe { gl::Disable(gl::BLEND); }
+		}
+
+		Framebuffer::unbind();
+		unsafe { gl::Viewport(0, 0, output_size.x, output_size.y); }
+
+		self.scene.get_target(0).unwrap().bind_to_slot(0);
+		self.bloom_chain[0].get_target(0).unwrap().bind_to_slot(1);
+
+		shaders.final_pass.use_program();
+		shaders.final_pass.set_uniforms(&FinalUniforms {
+			scene: 0,
+			bloom: 1,
+
+			bloom_enabled: (bloom_levels > 0) as i32,
+			bloom_intensity: settings.bloom.intensity,
+
+			tone_map_enabled: settings.tone_map.enabled as i32,
+			exposure: settings.tone_map.exposure,
+			white_point: settings.tone_map.white_point,
+
+			vignette_enabled: settings.vignette.enabled as i32,
+			vignette_strength: settings.vignette.strength,
+			vignette_radius: settings.vignette.radius,
+			vignette_softness: settings.vignette.softness,
+
+			grain_enabled: settings.grain.enabled as i32,
+			grain_intensity: settings.grain.intensity,
+
+			aspect: output_size.x as f32 / output_size.y.max(1) as f32,
+			time,
+		});
+
+		quad_mesh.draw(gl::TRIANGLES);
+
+		Texture::unbind();
+		Mesh::unbind();
+
+		unsafe {
+			gl::Enable(gl::BLEND);
+			gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+			gl::Enable(gl::DEPTH_TEST);
+		}
+	}
+}
+
+impl Target for PostStack {
+	fn resize(&mut self, size: Vec2i) {
+		self.scene.resize(size);
+
+		for (level, fb) in self.bloom_chain.iter_mut().enumerate() {
+			fb.resize(bloom_level_size(size, level));
+		}
+
+		self.size = size;
+	}
+
+	fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+// Level 0 is half the viewport size, and each level after that halves again
+fn bloom_level_size(size: Vec2i, level: usize) -> Vec2i {
+	let shift = level as u32 + 1;
+	Vec2i::new((size.x >> shift).max(1), (size.y >> shift).max(1))
+}
+
+fn texel_size(size: Vec2i) -> Vec2 {
+	Vec2::new(1.0 / size.x as f32, 1.0 / size.y as f32)
+}
@@ -0,0 +1,236 @@
+// Settings that trade image quality for frame time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QualityLevel {
+	pub name: &'static str,
+
+	// Internal render resolution relative to the viewport
+	pub resolution_scale: f32,
+
+	pub star_count: u32,
+	pub motion_blur_steps: u32,
+
+	// Upper bound on ray march iterations in star_compose.frag
+	pub march_steps: u32,
+}
+
+// Ordered from lowest to highest
+pub static QUALITY_LEVELS: &'static [QualityLevel] = &[
+	QualityLevel { name: "lowest", resolution_scale: 0.5, star_count: 150, motion_blur_steps: 12, march_steps: 8 },
+	QualityLevel { name: "low", resolution_scale: 0.65, star_count: 250, motion_blur_steps: 24, march_steps: 10 },
+	QualityLevel { name: "medium", resolution_scale: 0.8, star_count: 350, motion_blur_steps: 50, march_steps: 12 },
+	QualityLevel { name: "high", resolution_scale: 1.0, star_count: 500, motion_blur_steps: 100, march_steps: 16 },
+];
+
+// Frame times are smoothed over roughly this many frames
+const SMOOTHING_FRAMES: f64 = 10.0;
+
+// Frames longer than this are hitches (tab switches, shader compiles), not a sign of load
+const MAX_SAMPLE_FRAME_TIME: f64 = 0.25;
+
+// How long frame times have to stay over or under budget before the level changes
+const DOWNGRADE_DELAY: f64 = 0.5;
+const UPGRADE_DELAY: f64 = 4.0;
+
+// Dropping back down within this long after an upgrade counts as a failed upgrade
+const FAILED_UPGRADE_TIME: f64 = 10.0;
+
+// Measurements right after a change are skipped while targets are reallocated
+const SETTLE_TIME: f64 = 1.0;
+
+// Frame time relative to the budget that counts as over budget, or as having headroom.
+//	Browsers cap frame rate at vsync, so headroom has to be judged close to the budget
+const DOWNGRADE_THRESHOLD: f64 = 1.2;
+const UPGRADE_THRESHOLD: f64 = 1.05;
+
+// Tracks smoothed frame time and moves between QUALITY_LEVELS to keep it within budget.
+//	Upgrades that immediately have to be undone make the next upgrade wait longer, so that
+//	a device sitting on the edge of a level doesn't flip back and forth
+pub struct QualityManager {
+	pub adaptive: bool,
+
+	level: usize,
+	frame_budget: f64,
+	avg_frame_time: Option<f64>,
+
+	settle_timer: f64,
+	over_budget_time: f64,
+	under_budget_time: f64,
+
+	upgrade_backoff: u32,
+	last_change_was_upgrade: bool,
+	time_at_level: f64,
+}
+
+impl QualityManager {
+	pub fn new(target_fps: f64) -> Self {
+		QualityManager {
+			adaptive: true,
+
+			level: QUALITY_LEVELS.len() - 1,
+			frame_budget: 1.0 / target_fps,
+			avg_frame_time: None,
+
+			settle_timer: SETTLE_TIME,
+			over_budget_time: 0.0,
+			under_budget_time: 0.0,
+
+			upgrade_backoff: 0,
+			last_change_was_upgrade: false,
+			time_at_level: 0.0,
+		}
+	}
+
+	pub fn current(&self) -> &'static QualityLevel {
+		&QUALITY_LEVELS[self.level]
+	}
+
+	pub fn level(&self) -> usize {
+		self.level
+	}
+
+	pub fn avg_frame_time(&self) -> Option<f64> {
+		self.avg_frame_time
+	}
+
+	pub fn set_level(&mut self, level: usize) {
+		self.level = level.min(QUALITY_LEVELS.len() - 1);
+		self.reset_measurements();
+	}
+
+	// Feeds in the last frame time, and returns whether the quality level changed
+	pub fn update(&mut self, dt: f64) -> bool {
+		if !self.adaptive || dt <= 0.0 || dt > MAX_SAMPLE_FRAME_TIME {
+			return false
+		}
+
+		self.time_at_level += dt;
+
+		if self.settle_timer > 0.0 {
+			self.settle_timer -= dt;
+			return false
+		}
+
+		let avg = match self.avg_frame_time {
+			Some(avg) => avg + (dt - avg) / SMOOTHING_FRAMES,
+			None => dt,
+		};
+
+		self.avg_frame_time = Some(avg);
+
+		if avg > self.frame_budget * DOWNGRADE_THRESHOLD {
+			self.over_budget_time += dt;
+			self.under_budget_time = 0.0;
+		} else if avg < self.frame_budget * UPGRADE_THRESHOLD {
+			self.under_budget_time += dt;
+			self.over_budget_time = 0.0;
+		} else {
+			self.over_budget_time = 0.0;
+			self.under_budget_time = 0.0;
+		}
+
+		if self.over_budget_time > DOWNGRADE_DELAY && self.level > 0 {
+			if self.last_change_was_upgrade && self.time_at_level < FAILED_UPGRADE_TIME {
+				self.upgrade_backoff = (self.upgrade_backoff + 1).min(4);
+			}
+
+			self.level -= 1;
+			self.last_change_was_upgrade = false;
+			self.reset_measurements();
+			return true
+		}
+
+		let upgrade_delay = UPGRADE_DELAY * (1 << self.upgrade_backoff) as f64;
+
+		if self.under_budget_time > upgrade_delay && self.level + 1 < QUALITY_LEVELS.len() {
+			self.level += 1;
+			self.last_change_was_upgrade = true;
+			self.reset_measurements();
+			return true
+		}
+
+		false
+	}
+
+	fn reset_measurements(&mut self) {
+		self.avg_frame_time = None;
+		self.settle_timer = SETTLE_TIME;
+		self.time_at_level = 0.0;
+		self.over_budget_time = 0.0;
+		self.under_budget_time = 0.0;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FAST_FRAME: f64 = 1.0 / 120.0;
+	const SLOW_FRAME: f64 = 1.0 / 30.0;
+
+	// Feeds frames of length dt until the level changes, returning how long that took
+	fn run_until_change(quality: &mut QualityManager, dt: f64, max_time: f64) -> Option<f64> {
+		let mut time = 0.0;
+
+		while time < max_time {
+			time += dt;
+
+			if quality.update(dt) {
+				return Some(time)
+			}
+		}
+
+		None
+	}
+
+	#[test]
+	fn slow_frames_lower_level() {
+		let mut quality = QualityManager::new(60.0);
+		let top = QUALITY_LEVELS.len() - 1;
+
+		let time = run_until_change(&mut quality, SLOW_FRAME, 10.0).unwrap();
+		assert_eq!(quality.level(), top - 1);
+		assert!(time > SETTLE_TIME + DOWNGRADE_DELAY);
+
+		run_until_change(&mut quality, SLOW_FRAME, 10.0).unwrap();
+		assert_eq!(quality.level(), top - 2);
+	}
+
+	#[test]
+	fn fast_frames_raise_level_after_cooldown() {
+		let mut quality = QualityManager::new(60.0);
+		quality.set_level(0);
+
+		let time = run_until_change(&mut quality, FAST_FRAME, 20.0).unwrap();
+		assert_eq!(quality.level(), 1);
+		assert!(time > SETTLE_TIME + UPGRADE_DELAY);
+	}
+
+	#[test]
+	fn failed_upgrade_backs_off() {
+		let mut quality = QualityManager::new(60.0);
+		quality.set_level(0);
+
+		let first_upgrade = run_until_change(&mut quality, FAST_FRAME, 20.0).unwrap();
+		assert_eq!(quality.level(), 1);
+
+		// The new level turns out to be too slow, so it's dropped again straight away
+		run_until_change(&mut quality, SLOW_FRAME, 10.0).unwrap();
+		assert_eq!(quality.level(), 0);
+
+		// Headroom at the lower level doesn't bring it straight back up
+		assert_eq!(run_until_change(&mut quality, FAST_FRAME, first_upgrade), None);
+		assert_eq!(quality.level(), 0);
+
+		run_until_change(&mut quality, FAST_FRAME, 20.0).unwrap();
+		assert_eq!(quality.level(), 1);
+	}
+
+	#[test]
+	fn manual_mode_holds_level() {
+		let mut quality = QualityManager::new(60.0);
+		quality.adaptive = false;
+
+		assert_eq!(run_until_change(&mut quality, SLOW_FRAME, 10.0), None);
+		assert_eq!(quality.level(), QUALITY_LEVELS.len() - 1);
+	}
+}
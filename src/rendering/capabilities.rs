@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use ems;
+use rendering::gl;
+
+// Optional WebGL features. Extensions aren't enabled by default (see enable_extensions_by_default in main),
+//	so anything that needs one has to be requested here and checked before use
+#[derive(Copy, Clone, Debug)]
+pub struct Capabilities {
+	pub webgl2: bool,
+
+	pub element_index_uint: bool,
+	pub vertex_array_object: bool,
+	pub instanced_arrays: bool,
+	pub standard_derivatives: bool,
+
+	pub draw_buffers: bool,
+	pub max_color_attachments: u32,
+	pub max_draw_buffers: u32,
+
+	pub depth_texture: bool,
+	pub texture_float: bool,
+	pub texture_float_linear: bool,
+	pub texture_half_float: bool,
+	pub texture_half_float_linear: bool,
+	pub color_buffer_float: bool,
+	pub color_buffer_half_float: bool,
+}
+
+thread_local! {
+	static CAPABILITIES: Cell<Capabilities> = Cell::new(Capabilities {
+		webgl2: false,
+
+		element_index_uint: false,
+		vertex_array_object: false,
+		instanced_arrays: false,
+		standard_derivatives: false,
+
+		draw_buffers: false,
+		max_color_attachments: 1,
+		max_draw_buffers: 1,
+
+		depth_texture: false,
+		texture_float: false,
+		texture_float_linear: false,
+		texture_half_float: false,
+		texture_half_float_linear: false,
+		color_buffer_float: false,
+		color_buffer_half_float: false,
+	});
+}
+
+fn get_integer(name: u32) -> u32 {
+	let mut value = 0i32;
+	unsafe { gl::GetIntegerv(name, &mut value); }
+	value.max(1) as u32
+}
+
+fn enable_extension(context: ems::EmWebGLContext, name: &[u8]) -> bool {
+	unsafe { ems::emscripten_webgl_enable_extension(context, name.as_ptr()) != 0 }
+}
+
+pub fn init(context: ems::EmWebGLContext) {
+	let webgl2 = unsafe {
+		let mut attribs = ::std::mem::zeroed();
+		ems::emscripten_webgl_get_context_attributes(context, &mut attribs);
+		attribs.major_version >= 2
+	};
+
+	let draw_buffers = enable_extension(context, b"WEBGL_draw_buffers\0") || webgl2;
+
+	// Without draw buffers only COLOR_ATTACHMENT0 is valid
+	let (max_color_attachments, max_draw_buffers) = if draw_buffers {
+		(get_integer(gl::MAX_COLOR_ATTACHMENTS_EXT), get_integer(gl::MAX_DRAW_BUFFERS_EXT))
+	} else {
+		(1, 1)
+	};
+
+	// These extensions were promoted to core in WebGL2, where they can't be enabled
+	let caps = Capabilities {
+		webgl2,
+
+		element_index_uint: enable_extension(context, b"OES_element_index_uint\0") || webgl2,
+		vertex_array_object: enable_extension(context, b"OES_vertex_array_object\0") || webgl2,
+		instanced_arrays: enable_extension(context, b"ANGLE_instanced_arrays\0") || webgl2,
+		standard_derivatives: enable_extension(context, b"OES_standard_derivatives\0") || webgl2,
+
+		draw_buffers,
+		max_color_attachments,
+		max_draw_buffers,
+
+		depth_texture: enable_extension(context, b"WEBGL_depth_texture\0") || webgl2,
+		texture_float: enable_extension(context, b"OES_texture_float\0") || webgl2,
+		texture_float_linear: enable_extension(context, b"OES_texture_float_linear\0"),
+		texture_half_float: enable_extension(context, b"OES_texture_half_float\0") || webgl2,
+		texture_half_float_linear: enable_extension(context, b"OES_texture_half_float_linear\0") || webgl2,
+
+		// WebGL2 only has the EXT_ variant, which covers both float and half float
+		color_buffer_float: enable_extension(context, b"WEBGL_color_buffer_float\0")
+			|| enable_extension(context, b"EXT_color_buffer_float\0"),
+		color_buffer_half_float: enable_extension(context, b"EXT_color_buffer_half_float\0")
+			|| (webgl2 && enable_extension(context, b"EXT_color_buffer_float\0")),
+	};
+
+	CAPABILITIES.with(|c| c.set(caps));
+}
+
+pub fn get() -> Capabilities {
+	CAPABILITIES.with(|c| c.get())
+}
@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use rendering::gl;
+use rendering::capabilities;
+
+// Enums from WebGL2 and WebGL1 extensions that aren't in the GLES2 bindings
+pub const RED: u32 = 0x1903;
+pub const R32F: u32 = 0x822E;
+pub const RGBA16F: u32 = 0x881A;
+pub const RGBA32F: u32 = 0x8814;
+pub const HALF_FLOAT: u32 = 0x140B;
+pub const HALF_FLOAT_OES: u32 = 0x8D61;
+pub const DEPTH_COMPONENT24: u32 = 0x81A6;
+pub const DEPTH_STENCIL: u32 = 0x84F9;
+pub const DEPTH24_STENCIL8: u32 = 0x88F0;
+pub const UNSIGNED_INT_24_8: u32 = 0x84FA;
+pub const DEPTH_STENCIL_ATTACHMENT: u32 = 0x821A;
+
+// Arguments to TexImage2D for a given format
+#[derive(Copy, Clone, Debug)]
+pub struct TexImageFormat {
+	pub internal_format: u32,
+	pub format: u32,
+	pub ty: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorFormat {
+	Rgba8,
+	Rgba16F,
+	Rgba32F,
+	R32F,
+}
+
+impl ColorFormat {
+	pub fn is_supported(&self) -> bool {
+		let caps = capabilities::get();
+
+		match *self {
+			ColorFormat::Rgba8 => true,
+			ColorFormat::Rgba16F => caps.texture_half_float && caps.color_buffer_half_float,
+			ColorFormat::Rgba32F => caps.texture_float && caps.color_buffer_float,
+			ColorFormat::R32F => caps.webgl2 && caps.color_buffer_float,
+		}
+	}
+
+	pub fn fallback(&self) -> Option<ColorFormat> {
+		match *self {
+			ColorFormat::Rgba8 => None,
+			ColorFormat::Rgba16F => Some(ColorFormat::Rgba8),
+			ColorFormat::Rgba32F => Some(ColorFormat::Rgba16F),
+			ColorFormat::R32F => Some(ColorFormat::Rgba32F),
+		}
+	}
+
+	// Walks the fallback chain until a renderable format is found
+	pub fn resolve(self) -> ColorFormat {
+		let mut format = self;
+
+		while !format.is_supported() {
+			format = format.fallback().unwrap();
+		}
+
+		if format != self {
+			println!("{:?} render targets not supported, falling back to {:?}", self, format);
+		}
+
+		format
+	}
+
+	pub fn is_float(&self) -> bool {
+		*self != ColorFormat::Rgba8
+	}
+
+	// Float textures can only be linearly filtered with OES_texture_float_linear, and half float
+	//	textures with OES_texture_half_float_linear outside of WebGL2
+	pub fn supports_linear_filtering(&self) -> bool {
+		let caps = capabilities::get();
+
+		match *self {
+			ColorFormat::Rgba8 => true,
+			ColorFormat::Rgba16F => caps.texture_half_float_linear,
+			ColorFormat::Rgba32F | ColorFormat::R32F => caps.texture_float_linear,
+		}
+	}
+
+	pub fn tex_image_format(&self) -> TexImageFormat {
+		let webgl2 = capabilities::get().webgl2;
+
+		let (internal_format, format, ty) = match *self {
+			ColorFormat::Rgba8 => (gl::RGBA, gl::RGBA, gl::UNSIGNED_BYTE),
+			ColorFormat::Rgba16F if webgl2 => (RGBA16F, gl::RGBA, HALF_FLOAT),
+			ColorFormat::Rgba16F => (gl::RGBA, gl::RGBA, HALF_FLOAT_OES),
+			ColorFormat::Rgba32F if webgl2 => (RGBA32F, gl::RGBA, gl::FLOAT),
+			ColorFormat::Rgba32F => (gl::RGBA, gl::RGBA, gl::FLOAT),
+			ColorFormat::R32F => (R32F, RED, gl::FLOAT),
+		};
+
+		TexImageFormat { internal_format, format, ty }
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthFormat {
+	Depth16,
+	Depth24,
+	Depth24Stencil8,
+}
+
+impl DepthFormat {
+	pub fn is_supported(&self) -> bool {
+		// All depth texture formats come with WEBGL_depth_texture
+		capabilities::get().depth_texture
+	}
+
+	pub fn fallback(&self) -> Option<DepthFormat> {
+		match *self {
+			DepthFormat::Depth16 => None,
+			DepthFormat::Depth24 => Some(DepthFormat::Depth16),
+			DepthFormat::Depth24Stencil8 => Some(DepthFormat::Depth24),
+		}
+	}
+
+	pub fn resolve(self) -> DepthFormat {
+		let mut format = self;
+
+		while !format.is_supported() {
+			match format.fallback() {
+				Some(f) => format = f,
+				None => {
+					println!("Depth textures not supported, {:?} depth target will likely be incomplete", self);
+					return self
+				}
+			}
+		}
+
+		if format != self {
+			println!("{:?} depth targets not supported, falling back to {:?}", self, format);
+		}
+
+		format
+	}
+
+	pub fn has_stencil(&self) -> bool {
+		*self == DepthFormat::Depth24Stencil8
+	}
+
+	pub fn attachment(&self) -> u32 {
+		if self.has_stencil() {
+			DEPTH_STENCIL_ATTACHMENT
+		} else {
+			gl::DEPTH_ATTACHMENT
+		}
+	}
+
+	pub fn tex_image_format(&self) -> TexImageFormat {
+		let webgl2 = capabilities::get().webgl2;
+
+		let (internal_format, format, ty) = match *self {
+			DepthFormat::Depth16 if webgl2 => (gl::DEPTH_COMPONENT16, gl::DEPTH_COMPONENT, gl::UNSIGNED_SHORT),
+			DepthFormat::Depth16 => (gl::DEPTH_COMPONENT, gl::DEPTH_COMPONENT, gl::UNSIGNED_SHORT),
+			DepthFormat::Depth24 if webgl2 => (DEPTH_COMPONENT24, gl::DEPTH_COMPONENT, gl::UNSIGNED_INT),
+			DepthFormat::Depth24 => (gl::DEPTH_COMPONENT, gl::DEPTH_COMPONENT, gl::UNSIGNED_INT),
+			DepthFormat::Depth24Stencil8 if webgl2 => (DEPTH24_STENCIL8, DEPTH_STENCIL, UNSIGNED_INT_24_8),
+			DepthFormat::Depth24Stencil8 => (DEPTH_STENCIL, DEPTH_STENCIL, UNSIGNED_INT_24_8),
+		};
+
+		TexImageFormat { internal_format, format, ty }
+	}
+}
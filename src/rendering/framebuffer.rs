@@ -4,11 +4,67 @@ use common::*;
 use rendering::gl;
 
 use rendering::texture::*;
+use rendering::image::{Image, Rgba8, Rgba32F};
+use rendering::format::{ColorFormat, DepthFormat, TexImageFormat};
+use rendering::resource_tracking::{self, ResourceKind};
+use rendering::capabilities;
+
+use std::fmt;
+
+// Only in WebGL2
+const FRAMEBUFFER_INCOMPLETE_MULTISAMPLE: u32 = 0x8D56;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Attachment {
+	Color(u32),
+	Depth,
+}
+
+#[derive(Clone, Debug)]
+pub enum FramebufferError {
+	TooManyColorAttachments { requested: u32, max: u32 },
+	TooManyDrawBuffers { requested: u32, max: u32 },
+
+	// The attachment that fails on its own, if one could be isolated
+	IncompleteAttachment(Option<(Attachment, String)>),
+	MissingAttachment,
+	IncompleteDimensions,
+	Unsupported(Option<(Attachment, String)>),
+	IncompleteMultisample,
+	Unknown(u32),
+}
+
+impl fmt::Display for FramebufferError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		use self::FramebufferError::*;
+
+		match *self {
+			TooManyColorAttachments { requested, max } =>
+				write!(f, "Framebuffer requested {} color attachments but only {} are supported", requested, max),
+			TooManyDrawBuffers { requested, max } =>
+				write!(f, "Framebuffer requested {} draw buffers but only {} are supported", requested, max),
+
+			IncompleteAttachment(Some((attachment, ref format))) =>
+				write!(f, "Framebuffer attachment {:?} ({}) is incomplete", attachment, format),
+			IncompleteAttachment(None) => write!(f, "Framebuffer has an incomplete attachment"),
+
+			MissingAttachment => write!(f, "Framebuffer has no attachments"),
+			IncompleteDimensions => write!(f, "Framebuffer attachments differ in size"),
+
+			Unsupported(Some((attachment, ref format))) =>
+				write!(f, "Framebuffer attachment {:?} ({}) is not renderable on this device", attachment, format),
+			Unsupported(None) => write!(f, "Framebuffer combination of attachments is not supported on this device"),
+
+			IncompleteMultisample => write!(f, "Framebuffer attachments have mismatched sample counts"),
+			Unknown(status) => write!(f, "Framebuffer is incomplete (status 0x{:X})", status),
+		}
+	}
+}
 
 pub struct Framebuffer {
 	gl_handle: u32,
-	targets: Vec<Texture>,
-	depth_target: Option<Texture>,
+	targets: Vec<(Texture, ColorFormat)>,
+	depth_target: Option<(Texture, DepthFormat)>,
 	size: Vec2i,
 }
 
@@ -28,38 +84,170 @@ impl Framebuffer {
 	pub fn get_target(&mut self, id: usize) -> Option<&mut Texture> {
 		if id >= self.targets.len() { return None }
 
-		Some(&mut self.targets[id])
+		Some(&mut self.targets[id].0)
+	}
+
+	pub fn target_count(&self) -> usize {
+		self.targets.len()
+	}
+
+	pub fn get_target_format(&self, id: usize) -> Option<ColorFormat> {
+		self.targets.get(id).map(|t| t.1)
 	}
 
 	pub fn get_depth(&mut self) -> Option<&mut Texture> {
-		self.depth_target.as_mut()
+		self.depth_target.as_mut().map(|t| &mut t.0)
+	}
+
+	pub fn get_depth_format(&self) -> Option<DepthFormat> {
+		self.depth_target.as_ref().map(|t| t.1)
+	}
+
+	// Attaches each target on its own to a scratch framebuffer to find one that
+	//	is incomplete or unrenderable by itself
+	fn find_faulty_attachment(&self) -> Option<(Attachment, String)> {
+		let colors = self.targets.iter().enumerate()
+			.map(|(i, &(ref tex, format))| (gl::COLOR_ATTACHMENT0, Attachment::Color(i as u32), tex.gl_handle, format!("{:?}", format)));
+
+		let depth = self.depth_target.iter()
+			.map(|&(ref tex, format)| (format.attachment(), Attachment::Depth, tex.gl_handle, format!("{:?}", format)));
+
+		let mut result = None;
+
+		unsafe {
+			let mut scratch = 0;
+			gl::GenFramebuffers(1, &mut scratch);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, scratch);
+
+			for (gl_attachment, attachment, gl_handle, format) in colors.chain(depth) {
+				gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl_attachment, gl::TEXTURE_2D, gl_handle, 0);
+				let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+				gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl_attachment, gl::TEXTURE_2D, 0, 0);
+
+				if status != gl::FRAMEBUFFER_COMPLETE {
+					result = Some((attachment, format));
+					break
+				}
+			}
+
+			Framebuffer::unbind();
+			gl::DeleteFramebuffers(1, &scratch);
+		}
+
+		result
+	}
+
+	// Reads back the default framebuffer, e.g. for screenshots. Must be called before
+	//	control returns to the browser, since the drawing buffer isn't preserved
+	pub fn read_screen_pixels(size: Vec2i) -> Image<Rgba8> {
+		Framebuffer::unbind();
+		read_bound_pixels(size, gl::UNSIGNED_BYTE)
+	}
+
+	pub fn read_pixels(&self, target: usize) -> Option<Image<Rgba8>> {
+		self.with_read_target(target, |size| read_bound_pixels(size, gl::UNSIGNED_BYTE))
+	}
+
+	// Only float targets can be read back as floats
+	pub fn read_pixels_float(&self, target: usize) -> Option<Image<Rgba32F>> {
+		match self.get_target_format(target) {
+			Some(format) if format.is_float() => {}
+			_ => return None
+		}
+
+		self.with_read_target(target, |size| read_bound_pixels(size, gl::FLOAT))
+	}
+
+	// Pixels are only read from COLOR_ATTACHMENT0 in GLES2, so other targets are
+	//	attached to a scratch framebuffer for reading
+	fn with_read_target<F, R>(&self, target: usize, read: F) -> Option<R> where F: FnOnce(Vec2i) -> R {
+		let tex = match self.targets.get(target) {
+			Some(&(ref tex, _)) => tex,
+			None => return None
+		};
+
+		if target == 0 {
+			self.bind();
+			let result = read(self.size);
+			Framebuffer::unbind();
+			return Some(result)
+		}
+
+		unsafe {
+			let mut scratch = 0;
+			gl::GenFramebuffers(1, &mut scratch);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, scratch);
+			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, tex.gl_handle, 0);
+
+			let result = read(self.size);
+
+			Framebuffer::unbind();
+			gl::DeleteFramebuffers(1, &scratch);
+
+			Some(result)
+		}
 	}
 
 	pub fn resize(&mut self, nsize: Vec2i) {
 		if self.size == nsize { return }
 
-		unsafe {
-			for tex in self.targets.iter_mut() {
-				let _guard = tex.bind_guard();
+		for &mut (ref mut tex, format) in self.targets.iter_mut() {
+			allocate_target(tex.gl_handle, nsize, format.tex_image_format());
+			tex.size = nsize;
+		}
 
-				gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, nsize.x, nsize.y, 0, 
-					gl::RGBA, gl::UNSIGNED_BYTE, 0 as *const _);
-			}
+		if let Some((ref mut tex, format)) = self.depth_target {
+			allocate_target(tex.gl_handle, nsize, format.tex_image_format());
+			tex.size = nsize;
+		}
 
-			if let Some(ref tex) = self.depth_target {
-				let _guard = tex.bind_guard();
+		self.size = nsize;
+	}
+}
+
+impl Drop for Framebuffer {
+	fn drop(&mut self) {
+		resource_tracking::free(ResourceKind::Framebuffer, || unsafe {
+			gl::DeleteFramebuffers(1, &self.gl_handle);
+		});
+	}
+}
 
-				gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT as i32, nsize.x, nsize.y, 0, 
-					gl::DEPTH_COMPONENT, gl::UNSIGNED_INT, 0 as *const _);
+// Renders into each face of a cube map in turn. draw is called with the face bound as the
+//	render target and the viewport set to cover it, and is expected to set up a 90 degree
+//	projection looking through the face (see CubeFace::view_rotation)
+pub fn render_to_cube_faces<F>(cube: &CubeTexture, mut draw: F) -> Result<(), FramebufferError> where F: FnMut(CubeFace) {
+	let mut result = Ok(());
+
+	unsafe {
+		let mut scratch = 0;
+		gl::GenFramebuffers(1, &mut scratch);
+		gl::BindFramebuffer(gl::FRAMEBUFFER, scratch);
+		gl::Viewport(0, 0, cube.size, cube.size);
+
+		for &face in CubeFace::ALL.iter() {
+			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, face.gl_target(), cube.gl_handle, 0);
+
+			let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+			if status != gl::FRAMEBUFFER_COMPLETE {
+				let format = format!("{:?} cube map face {:?}", cube.format, face);
+				result = Err(FramebufferError::Unsupported(Some((Attachment::Color(0), format))));
+				break
 			}
+
+			draw(face);
 		}
 
-		self.size = nsize;
+		Framebuffer::unbind();
+		gl::DeleteFramebuffers(1, &scratch);
 	}
+
+	result
 }
 
 pub struct FramebufferBuilder {
 	fb: Framebuffer,
+	error: Option<FramebufferError>,
 }
 
 impl FramebufferBuilder {
@@ -74,7 +262,9 @@ impl FramebufferBuilder {
 			fb.bind();
 		}
 
-		FramebufferBuilder { fb }
+		resource_tracking::track_alloc(ResourceKind::Framebuffer);
+
+		FramebufferBuilder { fb, error: None }
 	}
 
 	pub fn new_unsized() -> Self {
@@ -89,63 +279,149 @@ impl FramebufferBuilder {
 			fb.bind();
 		}
 
-		FramebufferBuilder { fb }
+		resource_tracking::track_alloc(ResourceKind::Framebuffer);
+
+		FramebufferBuilder { fb, error: None }
 	}
 
-	pub fn finalize(self) -> Framebuffer {
+	// On failure the framebuffer and all of its targets are released
+	pub fn finalize(mut self) -> Result<Framebuffer, FramebufferError> {
+		if self.error.is_none() && self.fb.targets.len() > 1 {
+			self.enable_draw_buffers();
+		}
+
+		let status = unsafe { gl::CheckFramebufferStatus(gl::FRAMEBUFFER) };
 		Framebuffer::unbind();
 
-		self.fb
+		if let Some(error) = self.error {
+			return Err(error)
+		}
+
+		let fb = self.fb;
+
+		match status {
+			gl::FRAMEBUFFER_COMPLETE => Ok(fb),
+
+			gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => Err(FramebufferError::IncompleteAttachment(fb.find_faulty_attachment())),
+			gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => Err(FramebufferError::MissingAttachment),
+			gl::FRAMEBUFFER_INCOMPLETE_DIMENSIONS => Err(FramebufferError::IncompleteDimensions),
+			gl::FRAMEBUFFER_UNSUPPORTED => Err(FramebufferError::Unsupported(fb.find_faulty_attachment())),
+			FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => Err(FramebufferError::IncompleteMultisample),
+
+			_ => Err(FramebufferError::Unknown(status)),
+		}
 	}
 
-	pub fn add_depth(mut self) -> Self {
-		let mut gl_handle = 0;
+	// Without this only COLOR_ATTACHMENT0 is written to. Draw buffer state belongs
+	//	to the framebuffer so it only has to be set once
+	fn enable_draw_buffers(&mut self) {
+		let requested = self.fb.targets.len() as u32;
+		let max = capabilities::get().max_draw_buffers;
 
-		assert!(self.fb.depth_target.is_none(), "Framebuffer can only have one depth target");
+		if requested > max {
+			self.error = Some(FramebufferError::TooManyDrawBuffers { requested, max });
+			return
+		}
 
+		let buffers = (0..requested)
+			.map(|i| gl::COLOR_ATTACHMENT0 + i)
+			.collect::<Vec<_>>();
+
+		// Emscripten routes this to drawBuffers on WebGL2 and WEBGL_draw_buffers on WebGL1
 		unsafe {
-			gl::GenTextures(1, &mut gl_handle);
-			let _guard = TextureBindGuard::new_raw(gl_handle);
+			gl::DrawBuffersEXT(buffers.len() as i32, buffers.as_ptr());
+		}
+	}
 
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+	pub fn add_depth(self) -> Self {
+		self.add_depth_with_format(DepthFormat::Depth24)
+	}
+
+	pub fn add_depth_with_format(mut self, format: DepthFormat) -> Self {
+		assert!(self.fb.depth_target.is_none(), "Framebuffer can only have one depth target");
 
-			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT as i32, self.fb.size.x, self.fb.size.y, 0, 
-				gl::DEPTH_COMPONENT, gl::UNSIGNED_INT, 0 as *const _);
+		let format = format.resolve();
+		let gl_handle = create_target_texture(false);
+		allocate_target(gl_handle, self.fb.size, format.tex_image_format());
 
-			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, 
+		unsafe {
+			gl::FramebufferTexture2D(gl::FRAMEBUFFER, format.attachment(),
 				gl::TEXTURE_2D, gl_handle, 0);
 		}
 
-		self.fb.depth_target = Some(Texture{gl_handle, size: self.fb.size});
+		self.fb.depth_target = Some((Texture::from_raw(gl_handle, self.fb.size), format));
 
 		self
 	}
 
-	pub fn add_target(mut self) -> Self {
-		let mut gl_handle = 0;
+	pub fn add_target(self) -> Self {
+		self.add_target_with_format(ColorFormat::Rgba8)
+	}
 
+	pub fn add_target_with_format(mut self, format: ColorFormat) -> Self {
 		let next_target = self.fb.targets.len() as u32;
+		let max_targets = capabilities::get().max_color_attachments;
+
+		// Attaching past the limit is a GL error, so defer it to finalize instead
+		if next_target >= max_targets {
+			// Targets past the limit are never attached, so count each rejected one here
+			let requested = match self.error {
+				Some(FramebufferError::TooManyColorAttachments { requested, .. }) => requested + 1,
+				_ => next_target + 1,
+			};
+
+			self.error = Some(FramebufferError::TooManyColorAttachments { requested, max: max_targets });
+			return self
+		}
 
-		unsafe {
-			gl::GenTextures(1, &mut gl_handle);
-			let _guard = TextureBindGuard::new_raw(gl_handle);
-
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
-
-			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, self.fb.size.x, self.fb.size.y, 0, 
-				gl::RGBA, gl::UNSIGNED_BYTE, 0 as *const _);
+		let format = format.resolve();
+		let gl_handle = create_target_texture(format.supports_linear_filtering());
+		allocate_target(gl_handle, self.fb.size, format.tex_image_format());
 
+		unsafe {
 			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + next_target, gl::TEXTURE_2D, gl_handle, 0);
 		}
 
-		self.fb.targets.push(Texture{gl_handle, size: self.fb.size});
+		self.fb.targets.push((Texture::from_raw(gl_handle, self.fb.size), format));
 
 		self
 	}
-}
\ No newline at end of file
+}
+
+fn create_target_texture(linear: bool) -> u32 {
+	let mut gl_handle = 0;
+	let filter = if linear { gl::LINEAR } else { gl::NEAREST };
+
+	unsafe {
+		gl::GenTextures(1, &mut gl_handle);
+		let _guard = TextureBindGuard::new_raw(gl_handle);
+
+		gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+		gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+		gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+		gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+	}
+
+	gl_handle
+}
+
+fn allocate_target(gl_handle: u32, size: Vec2i, format: TexImageFormat) {
+	unsafe {
+		let _guard = TextureBindGuard::new_raw(gl_handle);
+
+		gl::TexImage2D(gl::TEXTURE_2D, 0, format.internal_format as i32, size.x, size.y, 0,
+			format.format, format.ty, 0 as *const _);
+	}
+}
+
+// Reads RGBA pixels of type P from the bound framebuffer. Rows are bottom up
+fn read_bound_pixels<P: Copy + Default>(size: Vec2i, ty: u32) -> Image<[P; 4]> {
+	let mut pixels = vec![[P::default(); 4]; (size.x * size.y) as usize];
+
+	unsafe {
+		gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+		gl::ReadPixels(0, 0, size.x, size.y, gl::RGBA, ty, pixels.as_mut_ptr() as *mut _);
+	}
+
+	Image::new(size, pixels)
+}
@@ -0,0 +1,54 @@
+#![allow(dead_code)]
+
+use rendering::types::*;
+
+pub type Rgba8 = [u8; 4];
+pub type Rgba32F = [f32; 4];
+
+// Rows are stored bottom up when read back from GL, see flip_vertical
+#[derive(Clone, Debug)]
+pub struct Image<P> {
+	pub size: Vec2i,
+	pub pixels: Vec<P>,
+}
+
+impl<P: Copy> Image<P> {
+	pub fn new(size: Vec2i, pixels: Vec<P>) -> Self {
+		assert!(pixels.len() == (size.x * size.y) as usize, "Pixel count doesn't match image size");
+		Image { size, pixels }
+	}
+
+	pub fn get(&self, pos: Vec2i) -> P {
+		self.pixels[(pos.x + pos.y * self.size.x) as usize]
+	}
+
+	pub fn rows(&self) -> ::std::slice::Chunks<P> {
+		self.pixels.chunks(self.size.x as usize)
+	}
+
+	pub fn flip_vertical(&mut self) {
+		let width = self.size.x as usize;
+		let height = self.size.y as usize;
+
+		for y in 0..height/2 {
+			let flipped_y = height - y - 1;
+
+			for x in 0..width {
+				self.pixels.swap(y * width + x, flipped_y * width + x);
+			}
+		}
+	}
+}
+
+impl Image<Rgba32F> {
+	// Clamps to [0, 1], so HDR values are lost
+	pub fn to_rgba8(&self) -> Image<Rgba8> {
+		let convert = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+
+		let pixels = self.pixels.iter()
+			.map(|&[r, g, b, a]| [convert(r), convert(g), convert(b), convert(a)])
+			.collect();
+
+		Image::new(self.size, pixels)
+	}
+}
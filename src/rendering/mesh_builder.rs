@@ -1,20 +1,24 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
 use rendering::gl;
 use rendering::types::*;
+use rendering::vertex::{VertexAttribute, VertexLayout};
+use rendering::capabilities;
+use rendering::resource_tracking::{self, ResourceKind};
 
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Vertex {
 	pos: Vec3,
 	normal: Vec3,
-	// uv: Vec2,
 }
 
 impl Vertex {
-	// pub fn new_col(pos: Vec3, col: Color, uv: Vec2) -> Self {
-	// 	Vertex {pos, col: col.to_vec3(), uv}
-	// }
-
 	pub fn new_normal(pos: Vec3, normal: Vec3) -> Self {
 		Vertex{pos, normal}
 	}
@@ -22,90 +26,501 @@ impl Vertex {
 	pub fn new(pos: Vec3) -> Self {
 		Vertex{pos, normal: Vec3::zero()}
 	}
+}
 
-	pub fn get_size() -> u32 {
-		use std::mem::size_of;
-		size_of::<Vertex>() as u32
+impl VertexLayout for Vertex {
+	fn attributes() -> &'static [VertexAttribute] {
+		static ATTRIBUTES: &'static [VertexAttribute] = &[
+			VertexAttribute::float("position", 3),
+			VertexAttribute::float("normal", 3),
+		];
+
+		ATTRIBUTES
+	}
+}
+
+// Bitwise identity, used for deduplication
+fn vertex_bytes<V: VertexLayout>(v: &V) -> &[u8] {
+	use std::mem::size_of;
+	use std::slice;
+
+	unsafe {
+		slice::from_raw_parts(v as *const V as *const u8, size_of::<V>())
+	}
+}
+
+fn vertex_hash<V: VertexLayout>(v: &V) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	vertex_bytes(v).hash(&mut hasher);
+	hasher.finish()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexType {
+	U16,
+	U32,
+}
+
+impl IndexType {
+	fn gl_type(&self) -> u32 {
+		match *self {
+			IndexType::U16 => gl::UNSIGNED_SHORT,
+			IndexType::U32 => gl::UNSIGNED_INT,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+	// Uploaded once and drawn many times
+	Static,
+	// Modified repeatedly, e.g. animated geometry
+	Dynamic,
+	// Respecified every frame
+	Stream,
+}
+
+impl BufferUsage {
+	fn gl_usage(&self) -> u32 {
+		match *self {
+			BufferUsage::Static => gl::STATIC_DRAW,
+			BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+			BufferUsage::Stream => gl::STREAM_DRAW,
+		}
 	}
 }
 
-pub struct MeshBuilder {
-	verts: Vec<Vertex>,
+pub struct MeshBuilder<V: VertexLayout = Vertex> {
+	verts: Vec<V>,
+	indices: Vec<u32>,
+
+	// Vertex hash to the index of the first vertex added with it
+	vert_lookup: HashMap<u64, u32>,
 }
 
-impl MeshBuilder {
+impl<V: VertexLayout> MeshBuilder<V> {
 	pub fn new() -> Self {
 		MeshBuilder {
 			verts: Vec::new(),
+			indices: Vec::new(),
+			vert_lookup: HashMap::new(),
 		}
 	}
 
 	pub fn clear(&mut self) {
 		self.verts.clear();
+		self.indices.clear();
+		self.vert_lookup.clear();
 	}
 
+	// Index buffers are only uploaded if some vertices are actually shared,
+	//	otherwise the mesh is drawn with DrawArrays as before
 	pub fn upload_to(&self, mesh: &mut Mesh) {
-		unsafe {
+		// Binding the index buffer below would otherwise modify whichever vertex array was last bound
+		Mesh::unbind();
+
+		mesh.upload_vertices(&self.verts);
+		mesh.attributes = V::attributes();
+		mesh.stride = V::get_size();
+
+		let is_trivial = self.indices.len() == self.verts.len()
+			&& self.indices.iter().enumerate().all(|(i, &idx)| i as u32 == idx);
+
+		if is_trivial {
 			mesh.count = self.verts.len() as _;
-			let size = Vertex::get_size() * mesh.count;
+			mesh.index_type = None;
+
+		} else {
+			mesh.count = self.indices.len() as _;
 
-			gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vbo);
-			gl::BufferData(gl::ARRAY_BUFFER, size as _, self.verts.as_ptr() as _, gl::STATIC_DRAW);
+			let index_type = if self.verts.len() <= 0x10000 {
+				IndexType::U16
+			} else {
+				assert!(capabilities::get().element_index_uint,
+					"Mesh has {} vertices, but 32 bit indices aren't supported", self.verts.len());
+				IndexType::U32
+			};
+
+			mesh.upload_indices(&self.indices, index_type);
 		}
+
+		mesh.update_vertex_array();
+	}
+
+	// Rewrites a range of vertices in place, without touching indices or reallocating.
+	//	The mesh must have been uploaded from a builder with the same layout and at least as many vertices
+	pub fn upload_range_to(&self, mesh: &mut Mesh, range: Range<usize>) {
+		mesh.update_vertices(range.start, &self.verts[range]);
+	}
+
+	pub fn get_vertex_count(&self) -> usize {
+		self.verts.len()
+	}
+
+	pub fn get_index_count(&self) -> usize {
+		self.indices.len()
+	}
+
+	// Adds a vertex that is never shared
+	pub fn add_vert(&mut self, v: V) {
+		let idx = self.verts.len() as u32;
+		self.verts.push(v);
+		self.indices.push(idx);
 	}
 
-	pub fn add_vert(&mut self, v: Vertex) {
+	// Adds a vertex, reusing an existing identical one if possible, and returns its index
+	pub fn add_shared_vert(&mut self, v: V) -> u32 {
+		let next_idx = self.verts.len() as u32;
+		let hash = vertex_hash(&v);
+		let existing = self.vert_lookup.get(&hash).cloned();
+
+		match existing {
+			// Hashes can collide, in which case the vertex just isn't shared
+			Some(idx) => if vertex_bytes(&self.verts[idx as usize]) == vertex_bytes(&v) {
+				return idx
+			},
+
+			None => { self.vert_lookup.insert(hash, next_idx); }
+		}
+
 		self.verts.push(v);
+		next_idx
+	}
+
+	pub fn add_triangle_indices(&mut self, i0: u32, i1: u32, i2: u32) {
+		self.indices.extend_from_slice(&[i0, i1, i2]);
 	}
 
-	pub fn add_quad(&mut self, vs: &[Vertex]) {
+	pub fn add_quad(&mut self, vs: &[V]) {
 		assert!(vs.len() >= 4);
 
-		self.verts.push(vs[0]);
-		self.verts.push(vs[1]);
-		self.verts.push(vs[2]);
+		let is = [
+			self.add_shared_vert(vs[0]),
+			self.add_shared_vert(vs[1]),
+			self.add_shared_vert(vs[2]),
+			self.add_shared_vert(vs[3]),
+		];
 
-		self.verts.push(vs[0]);
-		self.verts.push(vs[2]);
-		self.verts.push(vs[3]);
+		self.add_triangle_indices(is[0], is[1], is[2]);
+		self.add_triangle_indices(is[0], is[2], is[3]);
 	}
 
-	pub fn add_convex_poly(&mut self, vs: &[Vertex]) {
+	pub fn add_convex_poly(&mut self, vs: &[V]) {
 		assert!(vs.len() >= 3);
 
-		for i in 1..vs.len()-1 {
-			self.verts.push(vs[0]);
-			self.verts.push(vs[i]);
-			self.verts.push(vs[i+1]);
+		let is = vs.iter()
+			.map(|&v| self.add_shared_vert(v))
+			.collect::<Vec<_>>();
+
+		for i in 1..is.len()-1 {
+			self.add_triangle_indices(is[0], is[i], is[i+1]);
 		}
 	}
 }
 
 pub struct Mesh {
+	pub vao: u32,
 	pub vbo: u32,
+	pub ebo: u32,
 	pub count: u32,
+	pub index_type: Option<IndexType>,
+
+	pub usage: BufferUsage,
+	vbo_size: usize,
+
+	pub attributes: &'static [VertexAttribute],
+	pub stride: u32,
+
+	instance_vbo: u32,
+	pub instance_count: u32,
+	pub instance_attributes: &'static [VertexAttribute],
+	pub instance_stride: u32,
+
+	// Without instancing support, instance attributes are set as constant attributes
+	//	before each draw, so a copy of the instance data is kept around
+	instance_data: Vec<u8>,
 }
 
 impl Mesh {
 	pub fn new() -> Self {
+		Mesh::with_usage(BufferUsage::Static)
+	}
+
+	pub fn with_usage(usage: BufferUsage) -> Self {
+		let mut vao = 0;
+
+		if capabilities::get().vertex_array_object {
+			unsafe { gl::GenVertexArraysOES(1, &mut vao); }
+			resource_tracking::track_alloc(ResourceKind::VertexArray);
+		}
+
 		Mesh {
+			vao,
 			vbo: gl::pls_make_buffer(),
-			count: 0
+			ebo: 0,
+			count: 0,
+			index_type: None,
+
+			usage,
+			vbo_size: 0,
+
+			attributes: Vertex::attributes(),
+			stride: Vertex::get_size(),
+
+			instance_vbo: 0,
+			instance_count: 0,
+			instance_attributes: &[],
+			instance_stride: 0,
+
+			instance_data: Vec::new(),
 		}
 	}
 
-	pub fn bind(&self) {
+	// Dynamic and streaming buffers are orphaned rather than reallocated if the new data fits,
+	//	so the driver can hand back fresh storage instead of waiting on draws still using the old contents
+	fn upload_vertices<V: VertexLayout>(&mut self, verts: &[V]) {
+		let size = V::get_size() as usize * verts.len();
+
+		unsafe {
+			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+
+			if self.usage == BufferUsage::Static || size > self.vbo_size {
+				gl::BufferData(gl::ARRAY_BUFFER, size as _, verts.as_ptr() as _, self.usage.gl_usage());
+				self.vbo_size = size;
+			} else {
+				gl::BufferData(gl::ARRAY_BUFFER, self.vbo_size as _, 0 as *const _, self.usage.gl_usage());
+				gl::BufferSubData(gl::ARRAY_BUFFER, 0, size as _, verts.as_ptr() as _);
+			}
+		}
+	}
+
+	pub fn update_vertices<V: VertexLayout>(&mut self, first_vertex: usize, verts: &[V]) {
+		let stride = V::get_size() as usize;
+		let offset = first_vertex * stride;
+		let size = verts.len() * stride;
+
+		assert!(stride == self.stride as usize, "Vertex layout doesn't match mesh");
+		assert!(offset + size <= self.vbo_size, "Vertex update out of bounds of vertex buffer");
+
+		unsafe {
+			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+			gl::BufferSubData(gl::ARRAY_BUFFER, offset as _, size as _, verts.as_ptr() as _);
+		}
+	}
+
+	pub fn upload_instances<I: VertexLayout>(&mut self, instances: &[I]) {
+		use std::mem::size_of;
+		use std::slice;
+
+		let size = I::get_size() as usize * instances.len();
+
+		if capabilities::get().instanced_arrays {
+			if self.instance_vbo == 0 {
+				self.instance_vbo = gl::pls_make_buffer();
+			}
+
+			unsafe {
+				gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+				gl::BufferData(gl::ARRAY_BUFFER, size as _, instances.as_ptr() as _, self.usage.gl_usage());
+			}
+
+		} else {
+			for attrib in I::attributes().iter() {
+				assert!(attrib.ty == gl::FLOAT, "Instance attributes must be floats when instancing isn't supported");
+			}
+
+			let bytes = unsafe {
+				slice::from_raw_parts(instances.as_ptr() as *const u8, size_of::<I>() * instances.len())
+			};
+
+			self.instance_data.clear();
+			self.instance_data.extend_from_slice(bytes);
+		}
+
+		self.instance_count = instances.len() as u32;
+		self.instance_attributes = I::attributes();
+		self.instance_stride = I::get_size();
+
+		self.update_vertex_array();
+	}
+
+	fn upload_indices(&mut self, indices: &[u32], index_type: IndexType) {
+		if self.ebo == 0 {
+			self.ebo = gl::pls_make_buffer();
+		}
+
+		unsafe {
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+
+			match index_type {
+				IndexType::U16 => {
+					let indices = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+					gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * 2) as _, indices.as_ptr() as _, self.usage.gl_usage());
+				}
+
+				IndexType::U32 => {
+					gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * 4) as _, indices.as_ptr() as _, self.usage.gl_usage());
+				}
+			}
+		}
+
+		self.index_type = Some(index_type);
+	}
+
+	// Points attributes at this meshes vertex and instance buffers, and disables any that this mesh
+	//	doesn't provide so they can't read from another meshes buffer
+	fn specify_attributes(&self) {
+		use rendering::vertex::ATTRIBUTE_LOCATIONS;
+
+		let instanced = capabilities::get().instanced_arrays;
+		let instance_attributes = if instanced { self.instance_attributes } else { &[] };
+
 		unsafe {
+			for &(name, location) in ATTRIBUTE_LOCATIONS.iter() {
+				let is_used = self.attributes.iter()
+					.chain(instance_attributes.iter())
+					.any(|a| a.name == name);
+
+				if !is_used {
+					gl::DisableVertexAttribArray(location);
+				}
+			}
+
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, Vertex::get_size() as _, 0 as _);
-			gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, Vertex::get_size() as _, 12 as _);
-			// gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, Vertex::get_size() as _, 24 as _);
+			point_attributes(self.attributes, self.stride, if instanced { Some(0) } else { None });
+
+			if self.instance_vbo != 0 {
+				gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+				point_attributes(instance_attributes, self.instance_stride, Some(1));
+			}
+
+			if self.index_type.is_some() {
+				gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+			}
+		}
+	}
+
+	// Sets instance attributes as constant values, for drawing without instancing support
+	fn set_constant_instance_attributes(&self, instance: u32) {
+		let start = (instance * self.instance_stride) as usize;
+		let mut offset = start;
+
+		for attrib in self.instance_attributes.iter() {
+			let mut value = [0.0f32, 0.0, 0.0, 1.0];
+
+			for c in 0..attrib.components as usize {
+				let bytes = &self.instance_data[offset + c*4 .. offset + c*4 + 4];
+				value[c] = unsafe { *(bytes.as_ptr() as *const f32) };
+			}
+
+			unsafe {
+				gl::VertexAttrib4fv(attrib.location(), value.as_ptr());
+			}
+
+			offset += attrib.size_in_bytes() as usize;
+		}
+	}
+
+	// Records attribute state into the vertex array, if supported
+	fn update_vertex_array(&self) {
+		if self.vao == 0 { return }
+
+		unsafe {
+			gl::BindVertexArrayOES(self.vao);
+			self.specify_attributes();
+			gl::BindVertexArrayOES(0);
+		}
+	}
+
+	pub fn bind(&self) {
+		if self.vao != 0 {
+			unsafe { gl::BindVertexArrayOES(self.vao); }
+		} else {
+			self.specify_attributes();
+		}
+	}
+
+	pub fn unbind() {
+		if capabilities::get().vertex_array_object {
+			unsafe { gl::BindVertexArrayOES(0); }
 		}
 	}
 
 	pub fn draw(&self, mode: u32) {
+		self.draw_partial(mode, self.count);
+	}
+
+	// Draws only the first count elements (indices, or vertices if the mesh isn't indexed)
+	pub fn draw_partial(&self, mode: u32, count: u32) {
+		let count = count.min(self.count);
+
+		unsafe {
+			match self.index_type {
+				Some(ty) => gl::DrawElements(mode, count as _, ty.gl_type(), 0 as _),
+				None => gl::DrawArrays(mode, 0, count as _),
+			}
+		}
+	}
+
+	// Draws the mesh once per uploaded instance, falling back to a draw call per instance
+	//	if instancing isn't supported
+	pub fn draw_instanced(&self, mode: u32) {
+		self.draw_instanced_partial(mode, self.count);
+	}
+
+	pub fn draw_instanced_partial(&self, mode: u32, count: u32) {
+		let count = count.min(self.count);
+
+		if !capabilities::get().instanced_arrays {
+			for instance in 0..self.instance_count {
+				self.set_constant_instance_attributes(instance);
+				self.draw_partial(mode, count);
+			}
+
+			return
+		}
+
 		unsafe {
-			gl::DrawArrays(mode, 0, self.count as _);
+			match self.index_type {
+				Some(ty) => gl::DrawElementsInstancedANGLE(mode, count as _, ty.gl_type(), 0 as _, self.instance_count as _),
+				None => gl::DrawArraysInstancedANGLE(mode, 0, count as _, self.instance_count as _),
+			}
+		}
+	}
+}
+
+unsafe fn point_attributes(attributes: &[VertexAttribute], stride: u32, divisor: Option<u32>) {
+	let mut offset = 0;
+
+	for attrib in attributes.iter() {
+		let location = attrib.location();
+
+		gl::EnableVertexAttribArray(location);
+		gl::VertexAttribPointer(location, attrib.components, attrib.ty,
+			attrib.normalized as u8, stride as _, offset as _);
+
+		if let Some(divisor) = divisor {
+			gl::VertexAttribDivisorANGLE(location, divisor);
 		}
+
+		offset += attrib.size_in_bytes();
 	}
-}
\ No newline at end of file
+}
+
+impl Drop for Mesh {
+	fn drop(&mut self) {
+		if self.vao != 0 {
+			resource_tracking::free(ResourceKind::VertexArray, || unsafe { gl::DeleteVertexArraysOES(1, &self.vao) });
+		}
+
+		resource_tracking::free(ResourceKind::Buffer, || unsafe { gl::DeleteBuffers(1, &self.vbo) });
+
+		if self.instance_vbo != 0 {
+			resource_tracking::free(ResourceKind::Buffer, || unsafe { gl::DeleteBuffers(1, &self.instance_vbo) });
+		}
+
+		if self.ebo != 0 {
+			resource_tracking::free(ResourceKind::Buffer, || unsafe { gl::DeleteBuffers(1, &self.ebo) });
+		}
+	}
+}
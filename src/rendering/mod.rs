@@ -9,16 +9,23 @@ pub mod gl {
 		unsafe {
 			let mut vbo = 0u32;
 			GenBuffers(1, &mut vbo);
+			super::resource_tracking::track_alloc(super::resource_tracking::ResourceKind::Buffer);
 			vbo
 		}
 	}
 }
 
+pub mod resource_tracking;
+pub mod capabilities;
+pub mod format;
 pub mod types;
-pub mod shader;
+#[macro_use] pub mod shader;
 pub mod texture;
+pub mod image;
 pub mod framebuffer;
+pub mod render_graph;
 
+pub mod vertex;
 pub mod mesh_builder;
 
 pub use self::types::*;
@@ -0,0 +1,217 @@
+#![allow(dead_code)]
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+use common::*;
+use rendering::framebuffer::Framebuffer;
+
+// Anything sized to the viewport that passes render into
+pub trait Target: Any {
+	fn resize(&mut self, size: Vec2i);
+
+	// Called once all passes have run
+	fn end_frame(&mut self) {}
+
+	fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl Target for Framebuffer {
+	fn resize(&mut self, size: Vec2i) { Framebuffer::resize(self, size) }
+	fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+// A pair of framebuffers that swap every frame, so that passes can
+//	build on what was rendered in the previous frame
+pub struct PingPong {
+	framebuffers: [Framebuffer; 2],
+	current: usize,
+}
+
+impl PingPong {
+	pub fn new(a: Framebuffer, b: Framebuffer) -> Self {
+		PingPong { framebuffers: [a, b], current: 0 }
+	}
+
+	pub fn current(&mut self) -> &mut Framebuffer {
+		&mut self.framebuffers[self.current]
+	}
+
+	pub fn previous(&mut self) -> &mut Framebuffer {
+		&mut self.framebuffers[1 - self.current]
+	}
+}
+
+impl Target for PingPong {
+	fn resize(&mut self, size: Vec2i) {
+		for fb in self.framebuffers.iter_mut() {
+			fb.resize(size);
+		}
+	}
+
+	fn end_frame(&mut self) {
+		self.current = 1 - self.current;
+	}
+
+	fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+pub struct TargetId<T> {
+	index: usize,
+	_marker: PhantomData<T>,
+}
+
+impl<T> Clone for TargetId<T> {
+	fn clone(&self) -> Self { TargetId { index: self.index, _marker: PhantomData } }
+}
+
+impl<T> Copy for TargetId<T> {}
+
+// What a pass reads or writes, used to order passes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TargetRef {
+	Screen,
+	Current(usize),
+
+	// The half of a PingPong holding the previous frame
+	Previous(usize),
+}
+
+impl TargetRef {
+	pub fn current<T>(id: TargetId<T>) -> TargetRef { TargetRef::Current(id.index) }
+	pub fn previous(id: TargetId<PingPong>) -> TargetRef { TargetRef::Previous(id.index) }
+}
+
+pub struct Targets {
+	entries: Vec<(&'static str, Box<Target>)>,
+}
+
+impl Targets {
+	pub fn get<T: Target>(&mut self, id: TargetId<T>) -> &mut T {
+		self.entries[id.index].1.as_any_mut()
+			.downcast_mut::<T>()
+			.unwrap()
+	}
+}
+
+pub type PassFn<C> = fn(&mut C, &mut Targets);
+
+struct Pass<C> {
+	name: &'static str,
+	inputs: Vec<TargetRef>,
+	outputs: Vec<TargetRef>,
+	execute: PassFn<C>,
+}
+
+// Owns the viewport sized targets and runs passes in dependency order. A pass runs after
+//	every pass that writes one of its inputs, and passes writing the same target run in the
+//	order they were added
+pub struct RenderGraph<C> {
+	targets: Targets,
+	passes: Vec<Pass<C>>,
+	order: Vec<usize>,
+	size: Option<Vec2i>,
+}
+
+impl<C> RenderGraph<C> {
+	pub fn new() -> Self {
+		RenderGraph {
+			targets: Targets { entries: Vec::new() },
+			passes: Vec::new(),
+			order: Vec::new(),
+			size: None,
+		}
+	}
+
+	pub fn add_target<T: Target>(&mut self, name: &'static str, target: T) -> TargetId<T> {
+		self.targets.entries.push((name, Box::new(target)));
+		self.size = None;
+
+		TargetId { index: self.targets.entries.len() - 1, _marker: PhantomData }
+	}
+
+	pub fn add_pass(&mut self, name: &'static str, inputs: &[TargetRef], outputs: &[TargetRef], execute: PassFn<C>) {
+		self.passes.push(Pass {
+			name,
+			inputs: inputs.to_vec(),
+			outputs: outputs.to_vec(),
+			execute,
+		});
+
+		self.order = self.sort_passes();
+	}
+
+	pub fn targets(&mut self) -> &mut Targets {
+		&mut self.targets
+	}
+
+	// Only resizes targets when the size actually changes
+	pub fn resize(&mut self, size: Vec2i) {
+		if self.size == Some(size) { return }
+
+		for &mut (_, ref mut target) in self.targets.entries.iter_mut() {
+			target.resize(size);
+		}
+
+		self.size = Some(size);
+	}
+
+	pub fn execute(&mut self, ctx: &mut C) {
+		for &idx in self.order.iter() {
+			(self.passes[idx].execute)(ctx, &mut self.targets);
+		}
+
+		for &mut (_, ref mut target) in self.targets.entries.iter_mut() {
+			target.end_frame();
+		}
+	}
+
+	pub fn pass_names(&self) -> Vec<&'static str> {
+		self.order.iter().map(|&idx| self.passes[idx].name).collect()
+	}
+
+	fn sort_passes(&self) -> Vec<usize> {
+		let count = self.passes.len();
+		let mut dependencies = vec![Vec::new(); count];
+
+		for (idx, pass) in self.passes.iter().enumerate() {
+			for (other_idx, other) in self.passes.iter().enumerate() {
+				if other_idx == idx { continue }
+
+				let reads_output = pass.inputs.iter().any(|r| other.outputs.contains(r));
+				let writes_after = other_idx < idx && pass.outputs.iter().any(|r| other.outputs.contains(r));
+
+				if reads_output || writes_after {
+					dependencies[idx].push(other_idx);
+				}
+			}
+		}
+
+		// Repeatedly take the first pass in declaration order with nothing left to wait on
+		let mut order = Vec::with_capacity(count);
+		let mut done = vec![false; count];
+
+		while order.len() < count {
+			let next = (0..count)
+				.find(|&idx| !done[idx] && dependencies[idx].iter().all(|&dep| done[dep]));
+
+			match next {
+				Some(idx) => {
+					done[idx] = true;
+					order.push(idx);
+				}
+
+				None => {
+					let stuck = (0..count)
+						.filter(|&idx| !done[idx])
+						.map(|idx| self.passes[idx].name)
+						.collect::<Vec<_>>();
+
+					panic!("Render graph has a dependency cycle between passes {:?}", stuck);
+				}
+			}
+		}
+
+		order
+	}
+}
@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Counts of live GL objects owned by rendering types. Only tracked in debug builds,
+//	in release builds live_count always returns 0
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+	Buffer,
+	VertexArray,
+	Program,
+	Texture,
+	Framebuffer,
+}
+
+static LIVE_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_VERTEX_ARRAYS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_TEXTURES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_FRAMEBUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+static DISCARDING: AtomicBool = AtomicBool::new(false);
+
+fn counter(kind: ResourceKind) -> &'static AtomicUsize {
+	match kind {
+		ResourceKind::Buffer => &LIVE_BUFFERS,
+		ResourceKind::VertexArray => &LIVE_VERTEX_ARRAYS,
+		ResourceKind::Program => &LIVE_PROGRAMS,
+		ResourceKind::Texture => &LIVE_TEXTURES,
+		ResourceKind::Framebuffer => &LIVE_FRAMEBUFFERS,
+	}
+}
+
+pub fn track_alloc(kind: ResourceKind) {
+	if cfg!(debug_assertions) {
+		counter(kind).fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+pub fn track_free(kind: ResourceKind) {
+	if cfg!(debug_assertions) {
+		let prev = counter(kind).fetch_sub(1, Ordering::Relaxed);
+		assert!(prev > 0, "Freed more {:?} objects than were allocated", kind);
+	}
+}
+
+// Counts an object as freed, and deletes it with delete unless it's being discarded
+pub fn free<F: FnOnce()>(kind: ResourceKind, delete: F) {
+	if !DISCARDING.load(Ordering::Relaxed) {
+		delete();
+	}
+
+	track_free(kind);
+}
+
+// Drops objects that belonged to a lost context without deleting their handles, which mean
+//	nothing to the context that replaced it. They still count as freed
+pub fn discard<T>(resources: T) {
+	DISCARDING.store(true, Ordering::Relaxed);
+	drop(resources);
+	DISCARDING.store(false, Ordering::Relaxed);
+}
+
+pub fn live_count(kind: ResourceKind) -> usize {
+	counter(kind).load(Ordering::Relaxed)
+}
+
+pub fn total_live_count() -> usize {
+	live_count(ResourceKind::Buffer)
+		+ live_count(ResourceKind::VertexArray)
+		+ live_count(ResourceKind::Program)
+		+ live_count(ResourceKind::Texture)
+		+ live_count(ResourceKind::Framebuffer)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use std::thread;
+
+	const KINDS: [ResourceKind; 5] = [
+		ResourceKind::Buffer,
+		ResourceKind::VertexArray,
+		ResourceKind::Program,
+		ResourceKind::Texture,
+		ResourceKind::Framebuffer,
+	];
+
+	// Counters are global, so tests that touch them can't run alongside each other
+	static SERIAL: AtomicBool = AtomicBool::new(false);
+
+	struct SerialGuard;
+
+	impl SerialGuard {
+		fn lock() -> Self {
+			while SERIAL.compare_and_swap(false, true, Ordering::Acquire) {
+				thread::yield_now();
+			}
+
+			SerialGuard
+		}
+	}
+
+	impl Drop for SerialGuard {
+		fn drop(&mut self) {
+			SERIAL.store(false, Ordering::Release);
+		}
+	}
+
+	// Stands in for a GL object, recording whether its delete was called
+	struct Object<'a> {
+		deleted: &'a Cell<bool>,
+	}
+
+	impl<'a> Drop for Object<'a> {
+		fn drop(&mut self) {
+			let deleted = self.deleted;
+			free(ResourceKind::Texture, || deleted.set(true));
+		}
+	}
+
+	#[test]
+	fn counts_return_to_start() {
+		let _serial = SerialGuard::lock();
+		let start = total_live_count();
+
+		for &kind in KINDS.iter() {
+			let before = live_count(kind);
+
+			track_alloc(kind);
+			track_alloc(kind);
+
+			if cfg!(debug_assertions) {
+				assert_eq!(live_count(kind), before + 2);
+			}
+
+			free(kind, || {});
+			free(kind, || {});
+
+			assert_eq!(live_count(kind), before);
+		}
+
+		assert_eq!(total_live_count(), start);
+	}
+
+	#[test]
+	fn discard_frees_without_deleting() {
+		let _serial = SerialGuard::lock();
+		let start = total_live_count();
+		let deleted = Cell::new(false);
+
+		track_alloc(ResourceKind::Texture);
+		discard(Object { deleted: &deleted });
+
+		assert!(!deleted.get(), "Discarded object was deleted");
+		assert_eq!(total_live_count(), start);
+
+		// Outside of discard, dropping deletes as usual
+		track_alloc(ResourceKind::Texture);
+		drop(Object { deleted: &deleted });
+
+		assert!(deleted.get(), "Dropped object wasn't deleted");
+		assert_eq!(total_live_count(), start);
+	}
+}
@@ -1,23 +1,82 @@
 #![allow(dead_code)]
 
 use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
 use math::*;
 use rendering::gl;
+use rendering::vertex::ATTRIBUTE_LOCATIONS;
+use rendering::resource_tracking::{self, ResourceKind};
 
-#[derive(Copy, Clone)]
 pub struct Shader {
 	pub gl_handle: u32,
 
 	pub proj_loc: i32,
 	pub view_loc: i32,
+
+	uniform_locs: RefCell<HashMap<String, i32>>,
+
+	uniforms: Vec<ShaderVariable>,
+	attributes: Vec<ShaderVariable>,
+}
+
+// Describes an active uniform or attribute as reported by the driver after linking
+#[derive(Clone, Debug)]
+pub struct ShaderVariable {
+	pub name: String,
+	pub ty: u32,
+	pub size: i32,
+	pub location: i32,
+}
+
+impl ShaderVariable {
+	pub fn type_name(&self) -> &'static str {
+		match self.ty {
+			gl::FLOAT => "float",
+			gl::FLOAT_VEC2 => "vec2",
+			gl::FLOAT_VEC3 => "vec3",
+			gl::FLOAT_VEC4 => "vec4",
+			gl::INT => "int",
+			gl::INT_VEC2 => "ivec2",
+			gl::INT_VEC3 => "ivec3",
+			gl::INT_VEC4 => "ivec4",
+			gl::BOOL => "bool",
+			gl::BOOL_VEC2 => "bvec2",
+			gl::BOOL_VEC3 => "bvec3",
+			gl::BOOL_VEC4 => "bvec4",
+			gl::FLOAT_MAT2 => "mat2",
+			gl::FLOAT_MAT3 => "mat3",
+			gl::FLOAT_MAT4 => "mat4",
+			gl::SAMPLER_2D => "sampler2D",
+			gl::SAMPLER_CUBE => "samplerCube",
+			_ => "unknown",
+		}
+	}
+
+	pub fn is_sampler(&self) -> bool {
+		self.ty == gl::SAMPLER_2D || self.ty == gl::SAMPLER_CUBE
+	}
 }
 
 impl Shader {
 	pub fn new(vertex_shader_src: &str, fragment_shader_src: &str) -> Shader {
-		use std::ffi::{CStr, CString};
+		match Shader::try_new(vertex_shader_src, fragment_shader_src) {
+			Ok(shader) => shader,
+			Err(log) => {
+				println!("{}", log);
+				Shader::invalid()
+			}
+		}
+	}
+
+	// Returns the info log of whichever stage failed if compilation or linking fails
+	pub fn try_new(vertex_shader_src: &str, fragment_shader_src: &str) -> Result<Shader, String> {
+		use std::ffi::CString;
 		unsafe {
 			let (vs,fs) = (gl::CreateShader(gl::VERTEX_SHADER), gl::CreateShader(gl::FRAGMENT_SHADER));
 			let program = gl::CreateProgram();
+			let mut error = None;
 
 			for &(sh, src) in [(vs, vertex_shader_src), (fs, fragment_shader_src)].iter() {
 				let src = CString::new(src).unwrap();
@@ -26,100 +85,182 @@ impl Shader {
 
 				let mut status = 0i32;
 				gl::GetShaderiv(sh, gl::COMPILE_STATUS, &mut status);
-				if status == 0 {
+				if status == 0 && error.is_none() {
 					let mut buf = [0u8; 1024];
 					let mut len = 0i32;
 					gl::GetShaderInfoLog(sh, buf.len() as i32, &mut len, buf.as_mut_ptr() as _);
 
-					println!("{}", CStr::from_bytes_with_nul_unchecked(&buf[..len as usize]).to_str().unwrap());
+					error = Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned());
 				}
-				
+
 				gl::AttachShader(program, sh);
 			}
 
-			gl::LinkProgram(program);
-			gl::UseProgram(program);
+			if error.is_none() {
+				for &(name, location) in ATTRIBUTE_LOCATIONS.iter() {
+					let name = CString::new(name).unwrap();
+					gl::BindAttribLocation(program, location, name.as_ptr());
+				}
+
+				gl::LinkProgram(program);
+
+				let mut status = 0i32;
+				gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+				if status == 0 {
+					let mut buf = [0u8; 1024];
+					let mut len = 0i32;
+					gl::GetProgramInfoLog(program, buf.len() as i32, &mut len, buf.as_mut_ptr() as _);
+
+					error = Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned());
+				}
+			}
 
 			gl::DeleteShader(vs);
 			gl::DeleteShader(fs);
 
-			Shader {
+			if let Some(log) = error {
+				gl::DeleteProgram(program);
+				return Err(log)
+			}
+
+			gl::UseProgram(program);
+			resource_tracking::track_alloc(ResourceKind::Program);
+
+			let uniforms = reflect_variables(program, VariableKind::Uniform);
+			let attributes = reflect_variables(program, VariableKind::Attribute);
+
+			// Prime the location cache with everything the program actually has.
+			//	Array uniforms are reported as 'name[0]', but can also be looked up as 'name'
+			let mut uniform_locs = HashMap::new();
+			for u in uniforms.iter() {
+				if u.name.ends_with("[0]") {
+					uniform_locs.insert(u.name[..u.name.len()-3].to_string(), u.location);
+				}
+
+				uniform_locs.insert(u.name.clone(), u.location);
+			}
+
+			Ok(Shader {
 				gl_handle: program,
 
 				proj_loc: gl::GetUniformLocation(program, b"proj\0".as_ptr() as _),
 				view_loc: gl::GetUniformLocation(program, b"view\0".as_ptr() as _),
-			}
+
+				uniform_locs: RefCell::new(uniform_locs),
+
+				uniforms,
+				attributes,
+			})
 		}
 	}
 
-	pub const fn invalid() -> Shader {
+	pub fn invalid() -> Shader {
 		Shader {
 			gl_handle: 0,
 			proj_loc: 0,
 			view_loc: 0,
+
+			uniform_locs: RefCell::new(HashMap::new()),
+
+			uniforms: Vec::new(),
+			attributes: Vec::new(),
 		}
 	}
 
+	pub fn uniforms(&self) -> &[ShaderVariable] {
+		&self.uniforms
+	}
+
+	pub fn attributes(&self) -> &[ShaderVariable] {
+		&self.attributes
+	}
+
+	pub fn get_uniform_info(&self, name: &str) -> Option<&ShaderVariable> {
+		self.uniforms.iter().find(|u| u.name == name)
+	}
+
+	pub fn get_attribute_info(&self, name: &str) -> Option<&ShaderVariable> {
+		self.attributes.iter().find(|a| a.name == name)
+	}
+
 	pub fn use_program(&self) {
 		unsafe {
 			gl::UseProgram(self.gl_handle);
 		}
 	}
 
+	pub fn is_bound(&self) -> bool {
+		unsafe {
+			let mut id = 0i32;
+			gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut id as *mut _);
+			id as u32 == self.gl_handle
+		}
+	}
+
+	// Locations are looked up once per name and cached for the lifetime of the program
 	pub fn get_uniform_loc(&self, uniform: &str) -> i32 {
 		use std::ffi::CString;
 
-		unsafe {
-			// TODO: Make sure we're bound
+		if let Some(&loc) = self.uniform_locs.borrow().get(uniform) {
+			return loc
+		}
+
+		let loc = unsafe {
 			let cstr = CString::new(uniform).unwrap();
 			gl::GetUniformLocation(self.gl_handle, cstr.as_ptr())
+		};
+
+		// Misses are cached too, so this only fires once per name
+		if cfg!(debug_assertions) && loc < 0 {
+			let active = self.uniforms.iter()
+				.map(|u| u.name.as_str())
+				.collect::<Vec<_>>();
+
+			println!("Warning: shader {} has no active uniform '{}' (active uniforms: {})",
+				self.gl_handle, uniform, active.join(", "));
 		}
+
+		self.uniform_locs.borrow_mut().insert(uniform.to_string(), loc);
+		loc
+	}
+
+	pub fn set_uniforms<U: UniformBlock>(&self, block: &U) {
+		debug_assert!(self.is_bound(), "Tried to set uniform block on unbound shader");
+		block.apply(self);
+	}
+
+	pub fn set_uniform<V: UniformValue>(&self, uniform: &str, v: &V) {
+		debug_assert!(self.is_bound(), "Tried to set uniform '{}' on unbound shader", uniform);
+		v.set_at(self.get_uniform_loc(uniform));
 	}
 
 	pub fn set_uniform_mat(&self, uniform: &str, mat: &Mat4) {
-		self.set_uniform_mat_raw(self.get_uniform_loc(&uniform), &mat);
+		self.set_uniform(uniform, mat);
 	}
-	
+
 	pub fn set_uniform_mat_raw(&self, uniform: i32, mat: &Mat4) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::UniformMatrix4fv(uniform, 1, 0, mat.transpose().rows.as_ptr() as *const f32);
-		}
+		debug_assert!(self.is_bound(), "Tried to set uniform on unbound shader");
+		mat.set_at(uniform);
 	}
 
 	pub fn set_uniform_vec2(&self, uniform: &str, v: &Vec2) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::Uniform2f(self.get_uniform_loc(&uniform), v.x, v.y);
-		}
+		self.set_uniform(uniform, v);
 	}
 
 	pub fn set_uniform_vec3(&self, uniform: &str, v: &Vec3) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::Uniform3f(self.get_uniform_loc(&uniform), v.x, v.y, v.z);
-		}
+		self.set_uniform(uniform, v);
 	}
 
 	pub fn set_uniform_vec4(&self, uniform: &str, v: &Vec4) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::Uniform4f(self.get_uniform_loc(&uniform), v.x, v.y, v.z, v.w);
-		}
+		self.set_uniform(uniform, v);
 	}
 
 	pub fn set_uniform_i32(&self, uniform: &str, v: i32) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::Uniform1i(self.get_uniform_loc(&uniform), v);
-		}		
+		self.set_uniform(uniform, &v);
 	}
 
 	pub fn set_uniform_f32(&self, uniform: &str, v: f32) {
-		unsafe {
-			// TODO: Make sure we're bound
-			gl::Uniform1f(self.get_uniform_loc(&uniform), v);
-		}
+		self.set_uniform(uniform, &v);
 	}
 
 	pub fn set_proj(&self, mat: &Mat4) {
@@ -129,4 +270,129 @@ impl Shader {
 	pub fn set_view(&self, mat: &Mat4) {
 		self.set_uniform_mat_raw(self.view_loc, &mat);
 	}
-}
\ No newline at end of file
+}
+
+impl Drop for Shader {
+	fn drop(&mut self) {
+		if self.gl_handle == 0 { return }
+
+		resource_tracking::free(ResourceKind::Program, || unsafe {
+			gl::DeleteProgram(self.gl_handle);
+		});
+	}
+}
+
+pub trait UniformValue {
+	fn set_at(&self, loc: i32);
+}
+
+impl UniformValue for f32 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::Uniform1f(loc, *self); }
+	}
+}
+
+impl UniformValue for i32 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::Uniform1i(loc, *self); }
+	}
+}
+
+impl UniformValue for Vec2 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::Uniform2f(loc, self.x, self.y); }
+	}
+}
+
+impl UniformValue for Vec3 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::Uniform3f(loc, self.x, self.y, self.z); }
+	}
+}
+
+impl UniformValue for Vec4 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::Uniform4f(loc, self.x, self.y, self.z, self.w); }
+	}
+}
+
+impl UniformValue for Mat4 {
+	fn set_at(&self, loc: i32) {
+		unsafe { gl::UniformMatrix4fv(loc, 1, 0, self.transpose().rows.as_ptr() as *const f32); }
+	}
+}
+
+pub trait UniformBlock {
+	fn apply(&self, shader: &Shader);
+}
+
+// Declares a struct whose fields map onto named uniforms, and implements UniformBlock for it
+//	so that the whole struct can be applied with Shader::set_uniforms
+#[macro_export]
+macro_rules! uniform_block {
+	(
+		$(#[$attr:meta])*
+		pub struct $name:ident {
+			$( $field:ident : $ty:ty = $uniform:expr ),* $(,)*
+		}
+	) => {
+		$(#[$attr])*
+		pub struct $name {
+			$( pub $field: $ty ),*
+		}
+
+		impl ::rendering::shader::UniformBlock for $name {
+			fn apply(&self, shader: &::rendering::shader::Shader) {
+				use ::rendering::shader::UniformValue;
+				$( self.$field.set_at(shader.get_uniform_loc($uniform)); )*
+			}
+		}
+	};
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum VariableKind {
+	Uniform,
+	Attribute,
+}
+
+unsafe fn reflect_variables(program: u32, kind: VariableKind) -> Vec<ShaderVariable> {
+	use std::ffi::CString;
+
+	let (count_param, max_length_param) = match kind {
+		VariableKind::Uniform => (gl::ACTIVE_UNIFORMS, gl::ACTIVE_UNIFORM_MAX_LENGTH),
+		VariableKind::Attribute => (gl::ACTIVE_ATTRIBUTES, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH),
+	};
+
+	let mut count = 0i32;
+	let mut max_length = 0i32;
+	gl::GetProgramiv(program, count_param, &mut count);
+	gl::GetProgramiv(program, max_length_param, &mut max_length);
+
+	let mut buf = vec![0u8; max_length.max(1) as usize];
+
+	(0..count.max(0) as u32)
+		.map(|index| {
+			let mut len = 0i32;
+			let mut size = 0i32;
+			let mut ty = 0u32;
+
+			match kind {
+				VariableKind::Uniform => gl::GetActiveUniform(program, index, buf.len() as i32,
+					&mut len, &mut size, &mut ty, buf.as_mut_ptr() as _),
+				VariableKind::Attribute => gl::GetActiveAttrib(program, index, buf.len() as i32,
+					&mut len, &mut size, &mut ty, buf.as_mut_ptr() as _),
+			}
+
+			let name = String::from_utf8_lossy(&buf[..len as usize]).into_owned();
+			let cname = CString::new(name.as_str()).unwrap();
+
+			let location = match kind {
+				VariableKind::Uniform => gl::GetUniformLocation(program, cname.as_ptr()),
+				VariableKind::Attribute => gl::GetAttribLocation(program, cname.as_ptr()),
+			};
+
+			ShaderVariable { name, ty, size, location }
+		})
+		.collect()
+}
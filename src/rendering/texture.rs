@@ -2,12 +2,39 @@
 
 use rendering::gl;
 use rendering::types::*;
+use rendering::image::{Image, Rgba8};
+use rendering::format::ColorFormat;
+use rendering::capabilities;
+use rendering::resource_tracking::{self, ResourceKind};
+use ems;
+use png;
 
 use std::ops::Drop;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+	Clamp,
+	Repeat,
+	MirroredRepeat,
+}
+
+impl WrapMode {
+	fn to_gl(&self) -> u32 {
+		match *self {
+			WrapMode::Clamp => gl::CLAMP_TO_EDGE,
+			WrapMode::Repeat => gl::REPEAT,
+			WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+		}
+	}
+}
+
 pub struct Texture {
 	pub gl_handle: u32,
 	pub size: Vec2i,
+
+	// Requested wrap modes, which may be overridden for NPOT textures (see is_npot_restricted)
+	wrap: (WrapMode, WrapMode),
+	mipmapped: bool,
 }
 
 impl Texture {
@@ -24,7 +51,21 @@ impl Texture {
 			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
 		}
 
-		Texture { gl_handle, size: Vec2i::zero() }
+		Texture::from_raw(gl_handle, Vec2i::zero())
+	}
+
+	// Takes ownership of an already generated texture object. A handle of 0 owns nothing,
+	//	and isn't counted or deleted
+	pub fn from_raw(gl_handle: u32, size: Vec2i) -> Self {
+		if gl_handle != 0 {
+			resource_tracking::track_alloc(ResourceKind::Texture);
+		}
+
+		Texture {
+			gl_handle, size,
+			wrap: (WrapMode::Clamp, WrapMode::Clamp),
+			mipmapped: false,
+		}
 	}
 
 	pub fn bind_guard(&self) -> TextureBindGuard {
@@ -37,31 +78,40 @@ impl Texture {
 		tex
 	}
 
-	// pub fn from_png(data: &[u8]) -> Self {
-	// 	use png::{Decoder, Reader};
-
-	// 	let decoder = Decoder::new(data);
-	// 	let (info, mut reader) = decoder.read_info().unwrap();
-
-	// 	assert!(info.width.is_power_of_two(), "Textures must be POW2");
-	// 	assert!(info.height.is_power_of_two(), "Textures must be POW2");
-
-	// 	let mut buf = vec![0; info.buffer_size()];
-	// 	reader.next_frame(&mut buf).unwrap();
-
-	// 	let mut tex = Texture::new();
-	// 	let _bind_guard = TextureBindGuard::new(&tex);
+	pub fn from_image(image: &Image<Rgba8>) -> Self {
+		let mut tex = Texture::new();
+		tex.upload_image(image);
+		tex
+	}
 
-	// 	tex.size = Vec2i::new(info.width as i32, info.height as i32);
+	pub fn from_png(data: &[u8]) -> Result<Self, String> {
+		png::decode(data).map(|image| Texture::from_image(&image))
+	}
 
-	// 	unsafe {
-	// 		gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32,
-	// 			tex.size.x, tex.size.y, 0, gl::RGBA,
-	// 			gl::UNSIGNED_BYTE, buf.as_ptr() as *const _);
-	// 	}
+	// Fetches and decodes an image relative to the page. PNGs are decoded here,
+	//	anything else (e.g. jpg) is left to the browser
+	pub fn load_async<F>(url: &str, callback: F) where F: FnOnce(Result<Texture, String>) + 'static {
+		let url = url.to_string();
+		let suffix = url.rsplit('.').next().unwrap_or("").to_lowercase();
+
+		ems::fetch_async(&url.clone(), move |data| {
+			let data = match data {
+				Some(data) => data,
+				None => return callback(Err(format!("Failed to fetch {}", url))),
+			};
+
+			if suffix == "png" {
+				callback(Texture::from_png(data).map_err(|err| format!("Failed to decode {}: {}", url, err)));
+				return
+			}
 
-	// 	tex
-	// }
+			ems::decode_image_async(data.to_vec(), &suffix, move |image| {
+				callback(image
+					.map(|image| Texture::from_image(&image))
+					.ok_or_else(|| format!("Failed to decode {}", url)));
+			});
+		});
+	}
 
 	fn get_bound_id() -> u32 {
 		unsafe {
@@ -84,9 +134,6 @@ impl Texture {
 
 	pub fn upload_1d(&mut self, data: &[Color]) {
 		unsafe {
-			let len = data.len() as u32;
-			assert!(len.is_power_of_two(), "Textures must be POW2");
-
 			self.size = Vec2i::new(data.len() as i32, 1);
 
 			let mut v = Vec::with_capacity(data.len() * 4);
@@ -102,13 +149,13 @@ impl Texture {
 			let _bind_guard = TextureBindGuard::new(self);
 			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, self.size.x, 1, 0, gl::RGBA, gl::UNSIGNED_BYTE, v.as_ptr() as *const _);
 		}
+
+		self.on_level0_changed();
 	}
 
 	pub fn upload_2d(&mut self, data: &[Color], size: Vec2i) {
 		unsafe {
 			let len = data.len() as i32;
-			assert!((size.x as u32).is_power_of_two(), "Textures must be POW2");
-			assert!((size.y as u32).is_power_of_two(), "Textures must be POW2");
 			assert!(len >= size.x*size.y, "Passed slice not large enough");
 
 			self.size = size;
@@ -127,14 +174,97 @@ impl Texture {
 			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, self.size.x, self.size.y, 0,
 				gl::RGBA, gl::UNSIGNED_BYTE, v.as_ptr() as *const _);
 		}
+
+		self.on_level0_changed();
+	}
+
+	pub fn upload_image(&mut self, image: &Image<Rgba8>) {
+		self.size = image.size;
+
+		unsafe {
+			let _bind_guard = TextureBindGuard::new(self);
+
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, self.size.x, self.size.y, 0,
+				gl::RGBA, gl::UNSIGNED_BYTE, image.pixels.as_ptr() as *const _);
+		}
+
+		self.on_level0_changed();
+	}
+
+	// WebGL1 can only sample NPOT textures with clamped wrapping and without mipmaps
+	pub fn is_npot_restricted(&self) -> bool {
+		let pow2 = (self.size.x as u32).is_power_of_two() && (self.size.y as u32).is_power_of_two();
+		!pow2 && !capabilities::get().webgl2
+	}
+
+	pub fn set_wrap(&mut self, mode: WrapMode) {
+		self.set_wrap_st(mode, mode);
+	}
+
+	pub fn set_wrap_st(&mut self, s: WrapMode, t: WrapMode) {
+		self.wrap = (s, t);
+		self.apply_wrap();
+	}
+
+	// Returns false if mipmaps aren't supported for this texture
+	pub fn generate_mipmaps(&mut self) -> bool {
+		if self.is_npot_restricted() {
+			println!("Can't generate mipmaps for NPOT texture of size {:?}", self.size);
+			return false
+		}
+
+		unsafe {
+			let _bind_guard = TextureBindGuard::new(self);
+
+			gl::GenerateMipmap(gl::TEXTURE_2D);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+		}
+
+		self.mipmapped = true;
+		true
+	}
+
+	fn apply_wrap(&self) {
+		let (mut s, mut t) = self.wrap;
+
+		if self.is_npot_restricted() && (s, t) != (WrapMode::Clamp, WrapMode::Clamp) {
+			println!("NPOT texture of size {:?} can only be clamped", self.size);
+			s = WrapMode::Clamp;
+			t = WrapMode::Clamp;
+		}
+
+		unsafe {
+			let _bind_guard = TextureBindGuard::new(self);
+
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, s.to_gl() as i32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, t.to_gl() as i32);
+		}
+	}
+
+	// Existing mip levels no longer match, and a size change can
+	//	change which wrap modes are allowed
+	fn on_level0_changed(&mut self) {
+		if self.mipmapped && !self.generate_mipmaps() {
+			self.mipmapped = false;
+
+			unsafe {
+				let _bind_guard = TextureBindGuard::new(self);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+			}
+		}
+
+		self.apply_wrap();
 	}
 
 	pub fn linear(&mut self) {
 		unsafe {
 			let _bind_guard = TextureBindGuard::new(self);
 
+			let min_filter = if self.mipmapped { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+
 			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
 		}
 	}
 
@@ -148,6 +278,16 @@ impl Texture {
 	}
 }
 
+impl Drop for Texture {
+	fn drop(&mut self) {
+		if self.gl_handle == 0 { return }
+
+		resource_tracking::free(ResourceKind::Texture, || unsafe {
+			gl::DeleteTextures(1, &self.gl_handle);
+		});
+	}
+}
+
 pub struct TextureBindGuard {
 	prev_binding: Option<u32>,
 }
@@ -175,4 +315,138 @@ impl Drop for TextureBindGuard {
 			unsafe{ gl::BindTexture(gl::TEXTURE_2D, prev_binding); }
 		}
 	}
-}
\ No newline at end of file
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+	PositiveX, NegativeX,
+	PositiveY, NegativeY,
+	PositiveZ, NegativeZ,
+}
+
+impl CubeFace {
+	pub const ALL: [CubeFace; 6] = [
+		CubeFace::PositiveX, CubeFace::NegativeX,
+		CubeFace::PositiveY, CubeFace::NegativeY,
+		CubeFace::PositiveZ, CubeFace::NegativeZ,
+	];
+
+	pub fn gl_target(&self) -> u32 {
+		gl::TEXTURE_CUBE_MAP_POSITIVE_X + *self as u32
+	}
+
+	// Rotation from world space into the view space of a camera rendering this face
+	//	with a 90 degree fov. Each face is seen upside down, as cube map faces are
+	//	laid out with t pointing down the sides of the cube
+	pub fn view_rotation(&self) -> Quat {
+		use std::f32::consts::{PI, FRAC_1_SQRT_2};
+
+		match *self {
+			CubeFace::PositiveX => Quat::new(Vec3::new(FRAC_1_SQRT_2, 0.0,-FRAC_1_SQRT_2), PI),
+			CubeFace::NegativeX => Quat::new(Vec3::new(FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2), PI),
+			CubeFace::PositiveY => Quat::new(Vec3::new(1.0, 0.0, 0.0),-PI/2.0),
+			CubeFace::NegativeY => Quat::new(Vec3::new(1.0, 0.0, 0.0), PI/2.0),
+			CubeFace::PositiveZ => Quat::new(Vec3::new(1.0, 0.0, 0.0), PI),
+			CubeFace::NegativeZ => Quat::new(Vec3::new(0.0, 0.0, 1.0), PI),
+		}
+	}
+}
+
+// Cube maps are always sampled seamlessly in WebGL2. WebGL1 has no way to enable it,
+//	so edges may show seams there with linear filtering
+pub struct CubeTexture {
+	pub gl_handle: u32,
+	pub size: i32,
+	pub format: ColorFormat,
+}
+
+impl CubeTexture {
+	pub fn new(size: i32, format: ColorFormat) -> Self {
+		let format = format.resolve();
+		let image_format = format.tex_image_format();
+		let filter = if format.supports_linear_filtering() { gl::LINEAR } else { gl::NEAREST };
+
+		let mut gl_handle = 0;
+
+		unsafe {
+			gl::GenTextures(1, &mut gl_handle);
+			let _bind_guard = CubeTextureBindGuard::new_raw(gl_handle);
+
+			gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, filter as i32);
+			gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, filter as i32);
+			gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+			gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+			for face in CubeFace::ALL.iter() {
+				gl::TexImage2D(face.gl_target(), 0, image_format.internal_format as i32, size, size, 0,
+					image_format.format, image_format.ty, 0 as *const _);
+			}
+		}
+
+		resource_tracking::track_alloc(ResourceKind::Texture);
+
+		CubeTexture { gl_handle, size, format }
+	}
+
+	pub fn upload_face(&mut self, face: CubeFace, image: &Image<Rgba8>) {
+		assert!(self.format == ColorFormat::Rgba8, "Only Rgba8 cube maps can be uploaded to");
+		assert!(image.size == Vec2i::splat(self.size), "Cube map faces must be square and match the cube map size");
+
+		unsafe {
+			let _bind_guard = CubeTextureBindGuard::new_raw(self.gl_handle);
+
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			gl::TexImage2D(face.gl_target(), 0, gl::RGBA as i32, self.size, self.size, 0,
+				gl::RGBA, gl::UNSIGNED_BYTE, image.pixels.as_ptr() as *const _);
+		}
+	}
+
+	pub fn bind_to_slot(&self, slot: u32) {
+		unsafe {
+			gl::ActiveTexture(gl::TEXTURE0 + slot);
+			gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.gl_handle);
+		}
+	}
+
+	pub fn unbind() {
+		unsafe{ gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0); }
+	}
+}
+
+impl Drop for CubeTexture {
+	fn drop(&mut self) {
+		resource_tracking::free(ResourceKind::Texture, || unsafe {
+			gl::DeleteTextures(1, &self.gl_handle);
+		});
+	}
+}
+
+pub struct CubeTextureBindGuard {
+	prev_binding: Option<u32>,
+}
+
+impl CubeTextureBindGuard {
+	pub fn new_raw(new_binding: u32) -> Self {
+		let prev_binding = unsafe {
+			let mut id = 0i32;
+			gl::GetIntegerv(gl::TEXTURE_BINDING_CUBE_MAP, &mut id as *mut _);
+			id as u32
+		};
+
+		if prev_binding != new_binding {
+			unsafe{ gl::BindTexture(gl::TEXTURE_CUBE_MAP, new_binding); }
+			CubeTextureBindGuard{ prev_binding: Some(prev_binding) }
+		} else {
+			CubeTextureBindGuard { prev_binding: None }
+		}
+	}
+}
+
+impl Drop for CubeTextureBindGuard {
+	fn drop(&mut self) {
+		if let Some(prev_binding) = self.prev_binding {
+			unsafe{ gl::BindTexture(gl::TEXTURE_CUBE_MAP, prev_binding); }
+		}
+	}
+}
@@ -82,14 +82,27 @@ impl Ease<Color> for f32 {
 	impl_ease_for_color!(ease_bounce_inout);
 }
 
+// size is the size of the canvas backing store in device pixels, which everything is rendered at.
+//	css_size is the size the canvas is laid out at. Input positions are converted from css to
+//	device pixels with client_to_pixel as they arrive, so everything else works in device pixels
 #[derive(Copy, Clone, Debug)]
 pub struct Viewport {
 	pub size: Vec2i,
+	pub css_size: Vec2i,
+	pub pixel_ratio: f32,
 }
 
 impl Viewport {
 	pub fn new() -> Viewport {
-		Viewport{ size: Vec2i::zero() }
+		Viewport{ size: Vec2i::zero(), css_size: Vec2i::zero(), pixel_ratio: 1.0 }
+	}
+
+	pub fn set_css_size(&mut self, css_size: Vec2i, pixel_ratio: f32) {
+		let scaled = css_size.to_vec2() * pixel_ratio;
+
+		self.css_size = css_size;
+		self.pixel_ratio = pixel_ratio;
+		self.size = Vec2i::new((scaled.x.round() as i32).max(1), (scaled.y.round() as i32).max(1));
 	}
 
 	pub fn get_aspect(&self) -> f32 {
@@ -97,6 +110,12 @@ impl Viewport {
 		sw as f32 / sh as f32
 	}
 
+	// Converts a position in css pixels, as reported by input events, to device pixels
+	pub fn client_to_pixel(&self, pos: Vec2i) -> Vec2i {
+		let pos = pos.to_vec2() * self.pixel_ratio;
+		Vec2i::new(pos.x.round() as i32, pos.y.round() as i32)
+	}
+
 	pub fn client_to_gl_coords(&self, pos: Vec2i) -> Vec2 {
 		let (sw, sh) = self.size.to_vec2().to_tuple();
 		let pos = pos.to_vec2();
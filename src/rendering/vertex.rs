@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+
+use rendering::gl;
+
+// Attribute locations are fixed by name for every shader (see Shader::try_new),
+//	so that any mesh can be drawn with any shader that consumes a subset of its attributes.
+//	New attribute names need an entry here before they can be used in a VertexLayout
+pub static ATTRIBUTE_LOCATIONS: &'static [(&'static str, u32)] = &[
+	("position", 0),
+	("normal", 1),
+	("uv", 2),
+	("color", 3),
+	("point_size", 4),
+
+	// Per-instance attributes
+	("motion_blur_step", 5),
+];
+
+pub fn attribute_location(name: &str) -> u32 {
+	ATTRIBUTE_LOCATIONS.iter()
+		.find(|&&(n, _)| n == name)
+		.map(|&(_, loc)| loc)
+		.unwrap_or_else(|| panic!("Vertex attribute '{}' has no assigned location", name))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexAttribute {
+	pub name: &'static str,
+	pub components: i32,
+	pub ty: u32,
+	pub normalized: bool,
+}
+
+impl VertexAttribute {
+	pub const fn new(name: &'static str, components: i32, ty: u32, normalized: bool) -> Self {
+		VertexAttribute { name, components, ty, normalized }
+	}
+
+	pub const fn float(name: &'static str, components: i32) -> Self {
+		VertexAttribute::new(name, components, gl::FLOAT, false)
+	}
+
+	pub fn location(&self) -> u32 {
+		attribute_location(self.name)
+	}
+
+	pub fn size_in_bytes(&self) -> u32 {
+		let component_size = match self.ty {
+			gl::BYTE | gl::UNSIGNED_BYTE => 1,
+			gl::SHORT | gl::UNSIGNED_SHORT => 2,
+			gl::FLOAT => 4,
+			_ => panic!("Unsupported vertex attribute type {}", self.ty),
+		};
+
+		component_size * self.components as u32
+	}
+}
+
+// Describes how a vertex type is laid out in a vertex buffer. Attributes are assumed to be
+//	tightly packed in declaration order, so implementors should be #[repr(C)]
+pub trait VertexLayout: Copy {
+	fn attributes() -> &'static [VertexAttribute];
+
+	fn get_size() -> u32 {
+		use std::mem::size_of;
+		size_of::<Self>() as u32
+	}
+}
@@ -1,12 +1,56 @@
-pub static FB_SHADER_VERT_SRC: &'static str = include_str!("../assets/fb.vert");
-pub static FB_SHADER_FRAG_SRC: &'static str = include_str!("../assets/fb.frag");
-pub static STAR_SHADER_VERT_SRC: &'static str = include_str!("../assets/star.vert");
-pub static STAR_SHADER_FRAG_SRC: &'static str = include_str!("../assets/star.frag");
-pub static COLOR_SHADER_FRAG_SRC: &'static str = include_str!("../assets/color.frag");
-pub static CRYSTAL_SHADER_VERT_SRC: &'static str = include_str!("../assets/crystal.vert");
-pub static CRYSTAL_SHADER_FRAG_SRC: &'static str = include_str!("../assets/crystal.frag");
-
-pub static BASIC_TRANSFORM_SHADER_VERT_SRC: &'static str = include_str!("../assets/basic_transform.vert");
-
-pub static LINE_FUZZ_SHADER_FRAG_SRC: &'static str = include_str!("../assets/line_fuzz.frag");
-pub static STAR_COMPOSE_SHADER_FRAG_SRC: &'static str = include_str!("../assets/star_compose.frag");
+pub const FB_SHADER_VERT_SRC: &'static str = include_str!("../assets/fb.vert");
+pub const FB_SHADER_FRAG_SRC: &'static str = include_str!("../assets/fb.frag");
+pub const STAR_SHADER_VERT_SRC: &'static str = include_str!("../assets/star.vert");
+pub const STAR_SHADER_FRAG_SRC: &'static str = include_str!("../assets/star.frag");
+pub const STAR_STREAK_SHADER_VERT_SRC: &'static str = include_str!("../assets/star_streak.vert");
+pub const STAR_STREAK_SHADER_FRAG_SRC: &'static str = include_str!("../assets/star_streak.frag");
+pub const COLOR_SHADER_FRAG_SRC: &'static str = include_str!("../assets/color.frag");
+pub const CRYSTAL_SHADER_VERT_SRC: &'static str = include_str!("../assets/crystal.vert");
+pub const CRYSTAL_SHADER_FRAG_SRC: &'static str = include_str!("../assets/crystal.frag");
+
+pub const BASIC_TRANSFORM_SHADER_VERT_SRC: &'static str = include_str!("../assets/basic_transform.vert");
+
+pub const LINE_FUZZ_SHADER_FRAG_SRC: &'static str = include_str!("../assets/line_fuzz.frag");
+pub const STAR_COMPOSE_SHADER_FRAG_SRC: &'static str = include_str!("../assets/star_compose.frag");
+
+pub const POST_DOWNSAMPLE_SHADER_FRAG_SRC: &'static str = include_str!("../assets/post_downsample.frag");
+pub const POST_UPSAMPLE_SHADER_FRAG_SRC: &'static str = include_str!("../assets/post_upsample.frag");
+pub const POST_FINAL_SHADER_FRAG_SRC: &'static str = include_str!("../assets/post_final.frag");
+
+// Paths relative to assets/, used to refetch sources from the dev server (see mappings.sb)
+pub const FB_SHADER_VERT_PATH: &'static str = "fb.vert";
+pub const FB_SHADER_FRAG_PATH: &'static str = "fb.frag";
+pub const STAR_SHADER_VERT_PATH: &'static str = "star.vert";
+pub const STAR_SHADER_FRAG_PATH: &'static str = "star.frag";
+pub const STAR_STREAK_SHADER_VERT_PATH: &'static str = "star_streak.vert";
+pub const STAR_STREAK_SHADER_FRAG_PATH: &'static str = "star_streak.frag";
+pub const COLOR_SHADER_FRAG_PATH: &'static str = "color.frag";
+pub const CRYSTAL_SHADER_VERT_PATH: &'static str = "crystal.vert";
+pub const CRYSTAL_SHADER_FRAG_PATH: &'static str = "crystal.frag";
+
+pub const BASIC_TRANSFORM_SHADER_VERT_PATH: &'static str = "basic_transform.vert";
+
+pub const LINE_FUZZ_SHADER_FRAG_PATH: &'static str = "line_fuzz.frag";
+pub const STAR_COMPOSE_SHADER_FRAG_PATH: &'static str = "star_compose.frag";
+
+pub const POST_DOWNSAMPLE_SHADER_FRAG_PATH: &'static str = "post_downsample.frag";
+pub const POST_UPSAMPLE_SHADER_FRAG_PATH: &'static str = "post_upsample.frag";
+pub const POST_FINAL_SHADER_FRAG_PATH: &'static str = "post_final.frag";
+
+pub static SHADER_ASSETS: &'static [(&'static str, &'static str)] = &[
+	(FB_SHADER_VERT_PATH, FB_SHADER_VERT_SRC),
+	(FB_SHADER_FRAG_PATH, FB_SHADER_FRAG_SRC),
+	(STAR_SHADER_VERT_PATH, STAR_SHADER_VERT_SRC),
+	(STAR_SHADER_FRAG_PATH, STAR_SHADER_FRAG_SRC),
+	(STAR_STREAK_SHADER_VERT_PATH, STAR_STREAK_SHADER_VERT_SRC),
+	(STAR_STREAK_SHADER_FRAG_PATH, STAR_STREAK_SHADER_FRAG_SRC),
+	(COLOR_SHADER_FRAG_PATH, COLOR_SHADER_FRAG_SRC),
+	(CRYSTAL_SHADER_VERT_PATH, CRYSTAL_SHADER_VERT_SRC),
+	(CRYSTAL_SHADER_FRAG_PATH, CRYSTAL_SHADER_FRAG_SRC),
+	(BASIC_TRANSFORM_SHADER_VERT_PATH, BASIC_TRANSFORM_SHADER_VERT_SRC),
+	(LINE_FUZZ_SHADER_FRAG_PATH, LINE_FUZZ_SHADER_FRAG_SRC),
+	(STAR_COMPOSE_SHADER_FRAG_PATH, STAR_COMPOSE_SHADER_FRAG_SRC),
+	(POST_DOWNSAMPLE_SHADER_FRAG_PATH, POST_DOWNSAMPLE_SHADER_FRAG_SRC),
+	(POST_UPSAMPLE_SHADER_FRAG_PATH, POST_UPSAMPLE_SHADER_FRAG_SRC),
+	(POST_FINAL_SHADER_FRAG_PATH, POST_FINAL_SHADER_FRAG_SRC),
+];
@@ -0,0 +1,305 @@
+use common::*;
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TrackballSettings {
+	// Radians turned per unit dragged, where a unit is the shorter side of the viewport
+	pub sensitivity: f32,
+
+	// Rate at which angular velocity decays while coasting, per second
+	pub damping: f32,
+
+	// Release velocity is estimated from drag samples no older than this, in seconds
+	pub release_window: f64,
+	pub max_angular_speed: f32,
+
+	// Seconds without input before auto rotation takes over, and how quickly it does, per second
+	pub idle_delay: f64,
+	pub auto_rotate_speed: f32,
+	pub auto_rotate_response: f32,
+}
+
+impl Default for TrackballSettings {
+	fn default() -> Self {
+		TrackballSettings {
+			sensitivity: PI / 2.0,
+			damping: 1.2,
+
+			release_window: 0.1,
+			max_angular_speed: 4.0 * PI,
+
+			idle_delay: 3.0,
+			auto_rotate_speed: 0.15,
+			auto_rotate_response: 0.5,
+		}
+	}
+}
+
+// Rotation driven by dragging, that keeps spinning with the velocity it was released at and
+//	slows down with exponential damping. Once left alone it eases into a slow spin around the
+//	last axis it was spun around.
+//
+// Angular velocity is an axis scaled by radians per second, in the same space rotation is
+//	post-multiplied in. Time only advances through update, so it can be driven from a fixed
+//	timestep or stepped by hand without a browser
+pub struct Trackball {
+	pub settings: TrackballSettings,
+
+	pub rotation: Quat,
+	pub angular_velocity: Vec3,
+
+	dragging: bool,
+	drag_pos: Vec2,
+	pending_drag: Vec2,
+	samples: VecDeque<(f64, Vec2)>,
+
+	time: f64,
+	idle_time: f64,
+	auto_rotate_axis: Vec3,
+}
+
+impl Trackball {
+	pub fn new(settings: TrackballSettings) -> Self {
+		Trackball {
+			settings,
+
+			rotation: Quat::from_raw(0.0, 0.0, 0.0, 1.0),
+			angular_velocity: Vec3::zero(),
+
+			dragging: false,
+			drag_pos: Vec2::zero(),
+			pending_drag: Vec2::zero(),
+			samples: VecDeque::new(),
+
+			time: 0.0,
+			idle_time: 0.0,
+			auto_rotate_axis: Vec3::new(0.0, 1.0, 0.0),
+		}
+	}
+
+	// Positions are in units of the shorter side of the viewport. Grabbing stops any spin
+	pub fn begin_drag(&mut self, pos: Vec2) {
+		self.dragging = true;
+		self.drag_pos = pos;
+		self.pending_drag = Vec2::zero();
+		self.angular_velocity = Vec3::zero();
+		self.idle_time = 0.0;
+
+		self.samples.clear();
+		self.samples.push_back((self.time, pos));
+	}
+
+	// Movement is applied on the next update, so that it lands on a simulation step
+	pub fn drag(&mut self, pos: Vec2) {
+		if !self.dragging { return }
+
+		self.pending_drag = self.pending_drag + (pos - self.drag_pos);
+		self.drag_pos = pos;
+		self.idle_time = 0.0;
+
+		self.samples.push_back((self.time, pos));
+	}
+
+	pub fn end_drag(&mut self) {
+		if !self.dragging { return }
+
+		self.apply_pending_drag();
+		self.dragging = false;
+
+		let velocity = self.estimate_release_velocity();
+		self.angular_velocity = self.drag_to_rotation(velocity);
+
+		let speed = self.angular_velocity.length();
+
+		if speed > self.settings.max_angular_speed {
+			self.angular_velocity = self.angular_velocity * (self.settings.max_angular_speed / speed);
+		}
+
+		if speed > 0.0001 {
+			self.auto_rotate_axis = self.angular_velocity * (1.0 / speed);
+		}
+
+		self.samples.clear();
+	}
+
+	// Lets go without flinging, for when the end of a drag was never seen
+	pub fn cancel_drag(&mut self) {
+		self.apply_pending_drag();
+		self.dragging = false;
+		self.samples.clear();
+	}
+
+	pub fn update(&mut self, dt: f64) {
+		self.time += dt;
+
+		if self.dragging {
+			self.apply_pending_drag();
+			self.prune_samples();
+			return
+		}
+
+		let dtf = dt as f32;
+
+		self.idle_time += dt;
+
+		if self.idle_time > self.settings.idle_delay {
+			let target = self.auto_rotate_axis * self.settings.auto_rotate_speed;
+			let blend = 1.0 - (-self.settings.auto_rotate_response * dtf).exp();
+			self.angular_velocity = self.angular_velocity + (target - self.angular_velocity) * blend;
+		} else {
+			self.angular_velocity = self.angular_velocity * (-self.settings.damping * dtf).exp();
+		}
+
+		let angular_velocity = self.angular_velocity;
+		self.rotate_by(angular_velocity * dtf);
+	}
+
+	// Drag movement in viewport units to a rotation vector. Dragging right turns the
+	//	object about the vertical axis, dragging down turns it about the horizontal one
+	fn drag_to_rotation(&self, drag: Vec2) -> Vec3 {
+		Vec3::new(-drag.y, -drag.x, 0.0) * self.settings.sensitivity
+	}
+
+	fn apply_pending_drag(&mut self) {
+		let rotation = self.drag_to_rotation(self.pending_drag);
+		self.rotate_by(rotation);
+		self.pending_drag = Vec2::zero();
+	}
+
+	fn rotate_by(&mut self, rotation: Vec3) {
+		let angle = rotation.length();
+		if angle < 0.000001 { return }
+
+		let delta = Quat::new(rotation * (1.0 / angle), angle);
+		self.rotation = (self.rotation * delta).normalize();
+	}
+
+	fn prune_samples(&mut self) {
+		let oldest = self.time - self.settings.release_window;
+
+		// Always keep the newest sample, so a drag that has been held still still has a start
+		while self.samples.len() > 1 && self.samples[0].0 < oldest {
+			self.samples.pop_front();
+		}
+	}
+
+	// Average drag velocity over the release window, in viewport units per second. Zero if
+	//	the pointer stopped before being released
+	fn estimate_release_velocity(&mut self) -> Vec2 {
+		self.prune_samples();
+
+		let (first_time, first_pos) = match self.samples.front() {
+			Some(&sample) => sample,
+			None => return Vec2::zero()
+		};
+
+		let (last_time, last_pos) = *self.samples.back().unwrap();
+
+		// Samples are timestamped by update, so a quick flick can land within one step
+		let elapsed = (last_time - first_time).max(1.0 / 60.0);
+
+		if self.time - last_time > self.settings.release_window {
+			return Vec2::zero()
+		}
+
+		(last_pos - first_pos) * (1.0 / elapsed as f32)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 1.0 / 60.0;
+
+	fn assert_close(a: f32, b: f32, tolerance: f32) {
+		assert!((a - b).abs() <= tolerance, "{} is not within {} of {}", a, tolerance, b);
+	}
+
+	// Drags right at a constant speed for steps updates, in viewport units per second
+	fn drag_right(trackball: &mut Trackball, speed: f32, steps: u32) {
+		trackball.begin_drag(Vec2::zero());
+
+		for i in 1..steps+1 {
+			trackball.update(DT);
+			trackball.drag(Vec2::new(speed * DT as f32 * i as f32, 0.0));
+		}
+	}
+
+	#[test]
+	fn damping_is_independent_of_step_length() {
+		let settings = TrackballSettings { idle_delay: 100.0, ..TrackballSettings::default() };
+		let initial = Vec3::new(0.0, 2.0, 0.0);
+
+		let mut slow = Trackball::new(settings);
+		let mut fast = Trackball::new(settings);
+		slow.angular_velocity = initial;
+		fast.angular_velocity = initial;
+
+		for _ in 0..30 { slow.update(1.0 / 30.0); }
+		for _ in 0..144 { fast.update(1.0 / 144.0); }
+
+		let expected = 2.0 * (-settings.damping).exp();
+		assert_close(slow.angular_velocity.y, expected, 0.0001);
+		assert_close(fast.angular_velocity.y, expected, 0.0001);
+	}
+
+	#[test]
+	fn release_velocity_follows_drag() {
+		let mut trackball = Trackball::new(TrackballSettings::default());
+		drag_right(&mut trackball, 0.6, 6);
+		trackball.end_drag();
+
+		let expected = -0.6 * trackball.settings.sensitivity;
+		assert_close(trackball.angular_velocity.x, 0.0, 0.0001);
+		assert_close(trackball.angular_velocity.y, expected, 0.001);
+		assert_close(trackball.angular_velocity.z, 0.0, 0.0001);
+	}
+
+	#[test]
+	fn holding_still_before_release_stops_spin() {
+		let mut trackball = Trackball::new(TrackballSettings::default());
+		drag_right(&mut trackball, 0.6, 6);
+
+		let hold_steps = (trackball.settings.release_window / DT) as u32 + 2;
+		for _ in 0..hold_steps {
+			trackball.update(DT);
+		}
+
+		trackball.end_drag();
+		assert_close(trackball.angular_velocity.length(), 0.0, 0.000001);
+	}
+
+	#[test]
+	fn auto_rotates_once_idle() {
+		let mut trackball = Trackball::new(TrackballSettings::default());
+		let settings = trackball.settings;
+
+		let mut time = 0.0;
+		while time + DT < settings.idle_delay {
+			trackball.update(DT);
+			time += DT;
+		}
+
+		assert_close(trackball.angular_velocity.length(), 0.0, 0.000001);
+
+		for _ in 0..(20.0 / DT) as u32 {
+			trackball.update(DT);
+		}
+
+		assert_close(trackball.angular_velocity.x, 0.0, 0.0001);
+		assert_close(trackball.angular_velocity.y, settings.auto_rotate_speed, 0.001);
+		assert_close(trackball.angular_velocity.z, 0.0, 0.0001);
+	}
+
+	#[test]
+	fn release_speed_is_clamped() {
+		let mut trackball = Trackball::new(TrackballSettings::default());
+		drag_right(&mut trackball, 1000.0, 2);
+		trackball.end_drag();
+
+		assert_close(trackball.angular_velocity.length(), trackball.settings.max_angular_speed, 0.001);
+	}
+}